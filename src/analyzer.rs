@@ -1,67 +1,215 @@
 use crate::aux::AnalysisResult;
-use crate::fft::fft_chunk;
-use crate::notes::frequency_to_note;
-use crate::window::window_audio_samples;
+use crate::beat::BeatTracker;
+use crate::channels::ChannelMode;
+use crate::chroma::spectrum_chroma;
+use crate::fft::SpectrumAnalyzer;
+use crate::key::KeyTracker;
+use crate::multipitch::{detect_chord, estimate_pitches};
+use crate::notes::detect_band;
+use crate::stream::AnalysisChunk;
+use crate::window::StftConfig;
+use crate::yin::Yin;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::{sync::mpsc, time::Duration};
 
+// chunks further than this from where the last one ended start over
+const MAX_JUMP: Duration = Duration::from_millis(50);
+
+/// How the analyzer finds pitches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PitchStrategy {
+    /// strongest fft peaks of each frequency band, finds chords
+    #[default]
+    Bands,
+    /// one pitch from the yin tracker, for single voices and instruments
+    Yin,
+}
+
+impl PitchStrategy {
+    /// The other strategy, for switching while running.
+    pub fn toggled(&self) -> Self {
+        match self {
+            PitchStrategy::Bands => PitchStrategy::Yin,
+            PitchStrategy::Yin => PitchStrategy::Bands,
+        }
+    }
+}
+
+impl FromStr for PitchStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bands" | "fft" => Ok(PitchStrategy::Bands),
+            "yin" => Ok(PitchStrategy::Yin),
+            _ => Err(format!(
+                "unknown pitch strategy '{}', expected bands or yin",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PitchStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PitchStrategy::Bands => write!(f, "bands"),
+            PitchStrategy::Yin => write!(f, "yin"),
+        }
+    }
+}
+
 pub struct AudioAnalyzer {
     sample_rate: f32,
+    channels: usize,
+    channel_mode: ChannelMode,
+    // read for every chunk, so it can be switched while running
+    strategy: Arc<Mutex<PitchStrategy>>,
+    stft: StftConfig,
     result_sender: mpsc::Sender<AnalysisResult>,
 }
 
+// state carried from one chunk to the next
+struct Trackers {
+    key: KeyTracker,
+    beat: BeatTracker,
+    yin: Yin,
+    spectrum: SpectrumAnalyzer,
+    // samples not yet covered by a full window, and the time of the first
+    pending: Vec<f32>,
+    pending_start: Duration,
+}
+
 impl AudioAnalyzer {
-    pub fn new(sample_rate: f32, result_sender: mpsc::Sender<AnalysisResult>) -> Self {
+    pub fn new(
+        sample_rate: f32,
+        channels: usize,
+        channel_mode: ChannelMode,
+        strategy: PitchStrategy,
+        stft: StftConfig,
+        result_sender: mpsc::Sender<AnalysisResult>,
+    ) -> Self {
         Self {
             sample_rate,
+            channels,
+            channel_mode,
+            strategy: Arc::new(Mutex::new(strategy)),
+            stft,
             result_sender,
         }
     }
 
-    pub fn run(&self, receiver: mpsc::Receiver<(Duration, Vec<f32>)>) {
+    /// Shared handle to switch the pitch strategy of the running analyzer.
+    pub fn strategy(&self) -> Arc<Mutex<PitchStrategy>> {
+        self.strategy.clone()
+    }
+
+    pub fn run(&self, receiver: mpsc::Receiver<AnalysisChunk>) {
         let sample_rate = self.sample_rate;
+        let channels = self.channels;
+        let channel_mode = self.channel_mode;
+        let strategy = self.strategy.clone();
+        let stft = self.stft;
         let result_sender = self.result_sender.clone();
         thread::spawn(move || {
-            while let Ok((timestamp, samples)) = receiver.recv() {
-                Self::analyze_chunk(&samples, sample_rate, &result_sender, timestamp);
+            let mut trackers = Trackers {
+                key: KeyTracker::default(),
+                beat: BeatTracker::new(sample_rate),
+                yin: Yin::new(sample_rate, stft.window_size),
+                spectrum: SpectrumAnalyzer::from_stft(&stft),
+                pending: Vec::new(),
+                pending_start: Duration::ZERO,
+            };
+            while let Ok((timestamp, interleaved)) = receiver.recv() {
+                // analyze the selected channel signal
+                let samples = channel_mode.extract(&interleaved, channels);
+                let strategy = *strategy.lock().unwrap();
+                Self::analyze_chunk(
+                    &samples,
+                    sample_rate,
+                    strategy,
+                    &stft,
+                    &mut trackers,
+                    &result_sender,
+                    timestamp,
+                );
             }
         });
     }
 
+    // analyzes every window the chunk completes, windows overlap across chunks
     fn analyze_chunk(
         samples: &[f32],
         sample_rate: f32,
+        strategy: PitchStrategy,
+        stft: &StftConfig,
+        trackers: &mut Trackers,
         result_sender: &mpsc::Sender<AnalysisResult>,
         timestamp: Duration,
     ) {
-        let window_size = samples.len();
-        let mut windowed_samples = Vec::new();
-
-        // window the entire sample from Sender
-        let _ = window_audio_samples(samples, &mut windowed_samples, window_size - 1);
+        let (tempo, mut beats) = trackers.beat.process(samples, timestamp);
 
-        if windowed_samples.is_empty() {
-            return;
+        // seeks and dropouts break the overlap
+        let expected =
+            trackers.pending_start + samples_to_time(trackers.pending.len(), sample_rate);
+        if trackers.pending.is_empty() || timestamp.abs_diff(expected) > MAX_JUMP {
+            trackers.pending.clear();
+            trackers.pending_start = timestamp;
+            trackers.spectrum.reset();
         }
+        trackers.pending.extend_from_slice(samples);
 
-        // extract the one windowed sample
-        let first_window = windowed_samples.first().unwrap();
+        let mut consumed = 0;
+        for position in stft.window_starts(trackers.pending.len()) {
+            let window = &trackers.pending[position..position + stft.window_size];
+            if trackers.spectrum.process(window).is_err() {
+                return;
+            }
+            consumed = position + stft.hop_size;
+            let magnitudes = trackers.spectrum.magnitudes();
+            let frame_time = trackers.pending_start + samples_to_time(position, sample_rate);
 
-        if let Ok(frequency_bands) = fft_chunk(first_window, sample_rate, 3)
-            && !frequency_bands.is_empty()
-            && !frequency_bands[0].is_empty()
-        {
-            let note = frequency_bands
-                .iter()
-                .map(|band| frequency_to_note(band))
-                .collect::<Vec<String>>()
-                .join(" | ");
+            let chroma = spectrum_chroma(magnitudes, sample_rate, stft.fft_size);
+            let pitches = estimate_pitches(magnitudes, sample_rate, stft);
+            let chord = detect_chord(&pitches);
+            let bands = match strategy {
+                PitchStrategy::Bands => trackers
+                    .spectrum
+                    .bands(sample_rate, 3)
+                    .iter()
+                    .map(|band| detect_band(band, chord))
+                    .collect(),
+                // yin wants the signal as it is, not windowed
+                PitchStrategy::Yin => vec![trackers.yin.detect_band(window)],
+            };
 
-            let result = AnalysisResult { timestamp, note };
+            let result = AnalysisResult {
+                timestamp: frame_time,
+                bands,
+                chord,
+                pitches,
+                chroma,
+                key: trackers.key.update(&chroma, frame_time),
+                tempo,
+                // the chunk's beats go out once, with its first window
+                beats: std::mem::take(&mut beats),
+            };
 
             if result_sender.send(result).is_err() {
                 println!("Analysis result buffer failed to send");
             }
         }
+
+        trackers
+            .pending
+            .drain(..consumed.min(trackers.pending.len()));
+        trackers.pending_start += samples_to_time(consumed, sample_rate);
     }
 }
+
+fn samples_to_time(samples: usize, sample_rate: f32) -> Duration {
+    Duration::from_secs_f64(samples as f64 / sample_rate as f64)
+}
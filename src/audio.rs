@@ -1,125 +1,630 @@
-use symphonia::core::audio::Signal;
-use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
+use symphonia::core::audio::Channels;
+use symphonia::core::audio::{AudioBufferRef, Signal};
+use symphonia::core::codecs::{CODEC_TYPE_NULL, CodecParameters, Decoder, DecoderOptions};
 use symphonia::core::conv::IntoSample;
-use symphonia::core::errors::Error;
-use symphonia::core::formats::FormatOptions;
+use symphonia::core::errors::{Error, SeekErrorKind};
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track};
 use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use symphonia::core::meta::{
+    MetadataLog, MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey,
+};
+use symphonia::core::probe::{Descriptor, Instantiate, QueryDescriptor};
+use symphonia::core::units::{Time, TimeBase, TimeStamp};
+use symphonia::default::formats::{
+    AdtsReader, AiffReader, CafReader, FlacReader, IsoMp4Reader, MkvReader, MpaReader, OggReader,
+    WavReader,
+};
 
+use std::fmt;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
 use std::vec::Vec;
 
-pub fn decode_audio_wav(
-    path: &String,
+#[derive(Debug)]
+pub enum DecodeError {
+    /// the file could not be opened
+    Open(std::io::Error),
+    /// no format reader recognized the file
+    Probe(Error),
+    /// the container has no decodable audio track
+    NoTrack,
+    /// the requested track index does not exist or is not audio
+    InvalidTrack(usize),
+    /// no decoder is available for the track's codec
+    Codec(Error),
+    /// the decoder could not be re-created after a reset
+    Reset(Error),
+    /// reading or seeking the container failed
+    Format(Error),
+    /// an unrecoverable error while decoding a packet
+    Decode(Error),
+    /// the stream switched sample rate or channel count after a reset, which
+    /// playback and analysis set up for the first track can't follow
+    StreamChanged { sample_rate: f32, channels: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Open(err) => write!(f, "failed to open media: {}", err),
+            DecodeError::Probe(err) => write!(f, "unsupported format: {}", err),
+            DecodeError::NoTrack => write!(f, "no supported audio tracks"),
+            DecodeError::InvalidTrack(index) => write!(f, "no audio track at index {}", index),
+            DecodeError::Codec(err) => write!(f, "unsupported codec: {}", err),
+            DecodeError::Reset(err) => write!(f, "failed to reset decoder: {}", err),
+            DecodeError::Format(err) => write!(f, "failed to read media: {}", err),
+            DecodeError::Decode(err) => write!(f, "failed to decode packet: {}", err),
+            DecodeError::StreamChanged {
+                sample_rate,
+                channels,
+            } => write!(
+                f,
+                "stream changed to {} Hz with {} channels mid-playback",
+                sample_rate, channels
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Open(err) => Some(err),
+            DecodeError::NoTrack
+            | DecodeError::InvalidTrack(_)
+            | DecodeError::StreamChanged { .. } => None,
+            DecodeError::Probe(err)
+            | DecodeError::Codec(err)
+            | DecodeError::Reset(err)
+            | DecodeError::Format(err)
+            | DecodeError::Decode(err) => Some(err),
+        }
+    }
+}
+
+/// Describes one track of a media file.
+#[derive(Debug, Clone, Default)]
+pub struct TrackInfo {
+    /// position in the container's track list
+    pub index: usize,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+    pub container: &'static str,
+    pub codec: &'static str,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u32>,
+    pub channel_layout: String,
+    /// embedded cover art, front cover preferred
+    pub cover_art: Option<Vec<u8>>,
+}
+
+impl TrackInfo {
+    fn from_track(index: usize, track: &Track, container: &'static str) -> Self {
+        let params = &track.codec_params;
+
+        let duration = match (params.n_frames, params.time_base, params.sample_rate) {
+            (Some(frames), Some(time_base), _) => {
+                let time = time_base.calc_time(frames);
+                Some(Duration::from_secs_f64(time.seconds as f64 + time.frac))
+            }
+            (Some(frames), None, Some(rate)) => {
+                Some(Duration::from_secs_f64(frames as f64 / rate as f64))
+            }
+            _ => None,
+        };
+
+        Self {
+            index,
+            duration,
+            container,
+            codec: codec_name(params),
+            sample_rate: params.sample_rate,
+            bit_depth: params.bits_per_sample.or(params.bits_per_coded_sample),
+            channel_layout: channel_layout(params.channels),
+            ..Default::default()
+        }
+    }
+
+    // fills in fields that are still missing from a metadata revision
+    fn apply_metadata(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                _ => continue,
+            };
+            // riff and id3 values are often null or space padded
+            let value = tag.value.to_string();
+            let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            if field.is_none() && !value.is_empty() {
+                *field = Some(value.to_string());
+            }
+        }
+
+        if self.cover_art.is_none() {
+            let visuals = revision.visuals();
+            let cover = visuals
+                .iter()
+                .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+                .or(visuals.first());
+            self.cover_art = cover.map(|visual| visual.data.to_vec());
+        }
+    }
+
+    /// `artist - title`, or whichever of the two is known.
+    pub fn display_title(&self) -> Option<String> {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => Some(format!("{} - {}", artist, title)),
+            (None, Some(title)) => Some(title.clone()),
+            (Some(artist), None) => Some(artist.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+impl fmt::Display for TrackInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}/{}", self.index, self.container, self.codec)?;
+        if let Some(sample_rate) = self.sample_rate {
+            write!(f, " {} Hz", sample_rate)?;
+        }
+        if let Some(bit_depth) = self.bit_depth {
+            write!(f, " {}-bit", bit_depth)?;
+        }
+        write!(f, " {}", self.channel_layout)?;
+        if let Some(duration) = self.duration {
+            write!(f, " {:.1}s", duration.as_secs_f32())?;
+        }
+        if let Some(title) = self.display_title() {
+            write!(f, " | {}", title)?;
+        }
+        if let Some(album) = &self.album {
+            write!(f, " ({})", album)?;
+        }
+        if let Some(cover_art) = &self.cover_art {
+            write!(f, " [cover art {} bytes]", cover_art.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes packets from a media file on demand instead of loading
+/// the whole file into memory up front.
+pub struct StreamingDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
     sample_rate: f32,
-) -> Result<(Vec<f32>, f32), Box<dyn std::error::Error>> {
-    let src = std::fs::File::open(path).expect("failed to open media");
+    channels: usize,
+    total_frames: Option<usize>,
+    info: TrackInfo,
 
-    let mss = MediaSourceStream::new(Box::new(src), Default::default());
+    // decoded interleaved samples that have not been read yet
+    pending: Vec<f32>,
+    // frames to discard after an accurate seek lands before the target
+    skip_frames: usize,
+    // frames handed out so far, i.e. the read position
+    position: usize,
+    is_finished: bool,
+}
 
-    let mut hint = Hint::new();
-    hint.with_extension("wav");
+impl StreamingDecoder {
+    /// Opens the track at `track_index` in the container's track list,
+    /// or the first decodable audio track if `None`.
+    pub fn open_track(
+        path: &String,
+        sample_rate: f32,
+        track_index: Option<usize>,
+    ) -> Result<Self, DecodeError> {
+        let (mut format, container, mut probed_metadata) = open_format(path)?;
 
-    let meta_opts: MetadataOptions = Default::default();
-    let fmt_opts: FormatOptions = Default::default();
+        let (index, track) = match track_index {
+            Some(index) => format
+                .tracks()
+                .get(index)
+                .filter(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+                .map(|t| (index, t))
+                .ok_or(DecodeError::InvalidTrack(index))?,
+            None => format
+                .tracks()
+                .iter()
+                .enumerate()
+                .find(|(_, t)| t.codec_params.codec != CODEC_TYPE_NULL)
+                .ok_or(DecodeError::NoTrack)?,
+        };
 
-    let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &fmt_opts, &meta_opts)
-        .expect("unsupported format");
+        let decoder = make_decoder(&track.codec_params).map_err(DecodeError::Codec)?;
 
-    let mut format = probed.format;
+        let track_id = track.id;
+        let time_base = track.codec_params.time_base;
+        let sample_rate = track.codec_params.sample_rate.unwrap_or(sample_rate as u32) as f32;
+        let channels = track.codec_params.channels.map_or(1, |c| c.count()).max(1);
+        let total_frames = track.codec_params.n_frames.map(|n| n as usize);
 
-    let track = format
-        .tracks()
-        .iter()
-        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-        .expect("no supported audio tracks");
+        let mut info = TrackInfo::from_track(index, track, container);
+        apply_file_metadata(&mut info, format.as_mut(), &mut probed_metadata);
 
-    let dec_opts: DecoderOptions = Default::default();
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            time_base,
+            sample_rate,
+            channels,
+            total_frames,
+            info,
+            pending: Vec::new(),
+            skip_frames: 0,
+            position: 0,
+            is_finished: false,
+        })
+    }
 
-    let mut decoder = symphonia::default::get_codecs()
-        .make(&track.codec_params, &dec_opts)
-        .expect("unsupported codec");
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
 
-    let track_id = track.id;
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
 
-    let mut all_samples: Vec<f32> = Vec::new();
-    let sample_rate = track.codec_params.sample_rate.unwrap_or(sample_rate as u32) as f32;
+    /// Metadata and format details of the decoded track.
+    pub fn track_info(&self) -> &TrackInfo {
+        &self.info
+    }
 
-    loop {
+    /// Total number of frames in the track, if the container reports it.
+    pub fn total_frames(&self) -> Option<usize> {
+        self.total_frames
+    }
+
+    pub fn total_duration(&self) -> Option<Duration> {
+        self.total_frames
+            .map(|n| Duration::from_secs_f32(n as f32 / self.sample_rate))
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns up to `max_frames` interleaved frames, decoding more packets
+    /// as needed. An empty vec means the end of the track was reached.
+    pub fn read(&mut self, max_frames: usize) -> Result<Vec<f32>, DecodeError> {
+        let max_samples = max_frames * self.channels;
+        while self.pending.len() < max_samples && !self.is_finished {
+            self.decode_next_packet()?;
+        }
+
+        let count = max_samples.min(self.pending.len());
+        let chunk: Vec<f32> = self.pending.drain(..count).collect();
+        self.position += chunk.len() / self.channels;
+        Ok(chunk)
+    }
+
+    /// Seeks the underlying format reader and returns the new frame position.
+    pub fn seek(&mut self, time: Duration) -> Result<usize, DecodeError> {
+        let seek_to = SeekTo::Time {
+            time: Time::from(time.as_secs_f64()),
+            track_id: Some(self.track_id),
+        };
+
+        self.pending.clear();
+        self.decoder.reset();
+
+        match self.format.seek(SeekMode::Accurate, seek_to) {
+            Ok(seeked_to) => {
+                let required = self.timestamp_to_frames(seeked_to.required_ts);
+                let actual = self.timestamp_to_frames(seeked_to.actual_ts);
+
+                self.skip_frames = required.saturating_sub(actual);
+                self.position = required;
+                self.is_finished = false;
+            }
+            Err(Error::SeekError(SeekErrorKind::OutOfRange)) => {
+                // seeking past the end of the track
+                self.skip_frames = 0;
+                self.position = self.total_frames.unwrap_or(self.position);
+                self.is_finished = true;
+            }
+            Err(err) => return Err(DecodeError::Format(err)),
+        }
+
+        Ok(self.position)
+    }
+
+    fn timestamp_to_frames(&self, ts: TimeStamp) -> usize {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.sample_rate as f64).round() as usize
+            }
+            None => ts as usize,
+        }
+    }
+
+    // track list or codec parameters changed, e.g. chained ogg streams
+    fn reset_decoder(&mut self) -> Result<(), DecodeError> {
+        let track = self
+            .format
+            .tracks()
+            .iter()
+            .find(|t| t.id == self.track_id)
+            .or_else(|| {
+                self.format
+                    .tracks()
+                    .iter()
+                    .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            })
+            .ok_or(DecodeError::NoTrack)?;
+
+        check_unchanged(&track.codec_params, self.sample_rate, self.channels)?;
+        self.decoder = make_decoder(&track.codec_params).map_err(DecodeError::Reset)?;
+        self.track_id = track.id;
+        self.time_base = track.codec_params.time_base;
+        self.info.codec = codec_name(&track.codec_params);
+        Ok(())
+    }
+
+    fn decode_next_packet(&mut self) -> Result<(), DecodeError> {
         // get packet from media
-        let packet = match format.next_packet() {
+        let packet = match self.format.next_packet() {
             Ok(packet) => packet,
-            Err(Error::ResetRequired) => {
-                unimplemented!();
-            }
-            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(err) => {
-                panic!("{}", err);
+            Err(Error::ResetRequired) => return self.reset_decoder(),
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                self.is_finished = true;
+                return Ok(());
             }
+            Err(err) => return Err(DecodeError::Format(err)),
         };
 
         // consume new metadata that has been read after last packet
-        while !format.metadata().is_latest() {
-            format.metadata().pop();
+        while !self.format.metadata().is_latest() {
+            self.format.metadata().pop();
         }
 
-        if packet.track_id() != track_id {
-            continue;
+        if packet.track_id() != self.track_id {
+            return Ok(());
         }
 
         // decode packet by data type
-        match decoder.decode(&packet) {
-            Ok(decoded) => {
-                // store samples
-                let spec = *decoded.spec();
-                let channels = spec.channels.count();
-
-                match decoded {
-                    symphonia::core::audio::AudioBufferRef::U8(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::U16(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::U24(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::U32(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::S8(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::S16(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::S24(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::S32(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::F32(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                    symphonia::core::audio::AudioBufferRef::F64(buf) => {
-                        convert_samples_to_f32(&buf, channels, &mut all_samples);
-                    }
-                }
-            }
-            Err(Error::IoError(_)) => {
-                continue;
-            }
-            Err(Error::DecodeError(_)) => {
-                continue;
+        // io and decode errors only affect this packet so skip it
+        let start = self.pending.len();
+        match self.decoder.decode(&packet) {
+            Ok(decoded) => append_samples(decoded, self.channels, &mut self.pending),
+            Err(Error::IoError(_)) => return Ok(()),
+            Err(Error::DecodeError(_)) => return Ok(()),
+            Err(Error::ResetRequired) => return self.reset_decoder(),
+            Err(err) => return Err(DecodeError::Decode(err)),
+        }
+
+        // drop frames before an accurate seek target
+        if self.skip_frames > 0 {
+            let skipped = self
+                .skip_frames
+                .min((self.pending.len() - start) / self.channels);
+            self.pending.drain(start..start + skipped * self.channels);
+            self.skip_frames -= skipped;
+        }
+        Ok(())
+    }
+}
+
+// the streamer, output and analysis are set up for the first parameters and
+// can't follow a stream to a new rate or channel count, so that is an error.
+// parameters the new track leaves out are taken to be the same
+fn check_unchanged(
+    params: &CodecParameters,
+    sample_rate: f32,
+    channels: usize,
+) -> Result<(), DecodeError> {
+    let new_sample_rate = params.sample_rate.map_or(sample_rate, |rate| rate as f32);
+    let new_channels = params.channels.map_or(channels, |c| c.count());
+    if new_sample_rate != sample_rate || new_channels != channels {
+        return Err(DecodeError::StreamChanged {
+            sample_rate: new_sample_rate,
+            channels: new_channels,
+        });
+    }
+    Ok(())
+}
+
+fn make_decoder(codec_params: &CodecParameters) -> Result<Box<dyn Decoder>, Error> {
+    let dec_opts: DecoderOptions = Default::default();
+    symphonia::default::get_codecs().make(codec_params, &dec_opts)
+}
+
+/// Lists every track in the container, including ones that cannot be decoded.
+pub fn list_tracks(path: &String) -> Result<Vec<TrackInfo>, DecodeError> {
+    let (mut format, container, mut probed_metadata) = open_format(path)?;
+
+    let mut tracks: Vec<TrackInfo> = format
+        .tracks()
+        .iter()
+        .enumerate()
+        .map(|(index, track)| TrackInfo::from_track(index, track, container))
+        .collect();
+
+    // tags in these containers apply to the whole file
+    for info in tracks.iter_mut() {
+        apply_file_metadata(info, format.as_mut(), &mut probed_metadata);
+    }
+
+    Ok(tracks)
+}
+
+fn open_format(
+    path: &String,
+) -> Result<(Box<dyn FormatReader>, &'static str, MetadataLog), DecodeError> {
+    let src = std::fs::File::open(path).map_err(DecodeError::Open)?;
+    let mut mss = MediaSourceStream::new(Box::new(src), Default::default());
+
+    let meta_opts: MetadataOptions = Default::default();
+    let fmt_opts: FormatOptions = Default::default();
+
+    // what `Probe::format` does, keeping the reader that matched to name
+    // the container. it goes by marker bytes only and ignores any hint
+    let probe = symphonia::default::get_probe();
+    let mut metadata = MetadataLog::default();
+    let err = loop {
+        match probe.next(&mut mss) {
+            Ok(Instantiate::Format(instantiate)) => {
+                let container = container_name(instantiate).unwrap_or("unknown");
+                let format = instantiate(mss, &fmt_opts).map_err(DecodeError::Probe)?;
+                return Ok((format, container, metadata));
             }
-            Err(err) => {
-                panic!("{}", err)
+            Ok(Instantiate::Metadata(instantiate)) => {
+                match instantiate(&meta_opts).read_all(&mut mss) {
+                    Ok(revision) => metadata.push(revision),
+                    Err(err) => break err,
+                }
             }
+            Err(err) => break err,
         }
+    };
+
+    // files without marker bytes get the reader hinted by their extension,
+    // from the start of the same stream
+    match hinted_descriptor(path) {
+        Some(&Descriptor {
+            inst: Instantiate::Format(instantiate),
+            short_name,
+            ..
+        }) => {
+            mss.seek(SeekFrom::Start(0)).map_err(DecodeError::Open)?;
+            let format = instantiate(mss, &fmt_opts).map_err(DecodeError::Probe)?;
+            Ok((format, short_name, metadata))
+        }
+        _ => Err(DecodeError::Probe(err)),
+    }
+}
+
+// container metadata takes priority over tags found ahead of it, e.g. id3v2
+fn apply_file_metadata(
+    info: &mut TrackInfo,
+    format: &mut dyn FormatReader,
+    probed_metadata: &mut MetadataLog,
+) {
+    if let Some(revision) = format.metadata().skip_to_latest() {
+        info.apply_metadata(revision);
+    }
+    if let Some(revision) = probed_metadata.metadata().skip_to_latest() {
+        info.apply_metadata(revision);
+    }
+}
+
+fn channel_layout(channels: Option<Channels>) -> String {
+    match channels.map(|c| c.count()) {
+        None => "unknown layout".to_string(),
+        Some(1) => "mono".to_string(),
+        Some(2) => "stereo".to_string(),
+        Some(count) => format!("{} channels", count),
+    }
+}
+
+fn codec_name(codec_params: &CodecParameters) -> &'static str {
+    symphonia::default::get_codecs()
+        .get_codec(codec_params.codec)
+        .map_or("unknown", |descriptor| descriptor.short_name)
+}
+
+// descriptors of every container reader registered in the default probe
+fn container_descriptors() -> impl Iterator<Item = &'static Descriptor> {
+    [
+        WavReader::query(),
+        AiffReader::query(),
+        FlacReader::query(),
+        MpaReader::query(),
+        AdtsReader::query(),
+        OggReader::query(),
+        MkvReader::query(),
+        IsoMp4Reader::query(),
+        CafReader::query(),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+// container reader matching the file extension, if any
+fn hinted_descriptor(path: &str) -> Option<&'static Descriptor> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())?
+        .to_ascii_lowercase();
+
+    container_descriptors().find(|descriptor| descriptor.extensions.contains(&extension.as_str()))
+}
+
+// short name of the container the probe finds first in `mss`, skipping
+// metadata ahead of it the same way
+// how a format reader is made, as matched by the probe
+type FormatInstantiate =
+    fn(MediaSourceStream, &FormatOptions) -> Result<Box<dyn FormatReader>, Error>;
+
+// short name of the container read by `instantiate`
+fn container_name(instantiate: FormatInstantiate) -> Option<&'static str> {
+    container_descriptors()
+        .find(|descriptor| match descriptor.inst {
+            Instantiate::Format(other) => std::ptr::fn_addr_eq(instantiate, other),
+            Instantiate::Metadata(_) => false,
+        })
+        .map(|descriptor| descriptor.short_name)
+}
+
+/// A fully decoded file.
+pub struct DecodedAudio {
+    /// interleaved samples
+    pub samples: Vec<f32>,
+    pub sample_rate: f32,
+    pub channels: usize,
+    pub info: TrackInfo,
+}
+
+/// Decodes the whole file into interleaved samples. Works with any
+/// container and codec symphonia was built with.
+pub fn decode_audio(
+    path: &String,
+    sample_rate: f32,
+    track_index: Option<usize>,
+) -> Result<DecodedAudio, DecodeError> {
+    let mut decoder = StreamingDecoder::open_track(path, sample_rate, track_index)?;
+    let channels = decoder.channels();
+
+    let mut all_samples: Vec<f32> =
+        Vec::with_capacity(decoder.total_frames().unwrap_or(0) * channels);
+    loop {
+        let chunk = decoder.read(decoder.sample_rate() as usize)?;
+        if chunk.is_empty() {
+            break;
+        }
+        all_samples.extend(chunk);
+    }
+
+    Ok(DecodedAudio {
+        samples: all_samples,
+        sample_rate: decoder.sample_rate(),
+        channels,
+        info: decoder.track_info().clone(),
+    })
+}
+
+fn append_samples(decoded: AudioBufferRef, channels: usize, all_samples: &mut Vec<f32>) {
+    match decoded {
+        AudioBufferRef::U8(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::U16(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::U24(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::U32(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::S8(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::S16(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::S24(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::S32(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::F32(buf) => convert_samples_to_f32(&buf, channels, all_samples),
+        AudioBufferRef::F64(buf) => convert_samples_to_f32(&buf, channels, all_samples),
     }
-    Ok((all_samples, sample_rate))
 }
 
 fn convert_samples_to_f32<S>(
@@ -129,22 +634,57 @@ fn convert_samples_to_f32<S>(
 ) where
     S: symphonia::core::sample::Sample + IntoSample<f32> + Copy,
 {
-    if channels == 1 {
-        // mono: convert all samples directly
-        let samples = buf.chan(0);
-        for &sample in samples {
-            all_samples.push(sample.into_sample());
-        }
-    } else {
-        // multi-channel: mix to mono by averaging all channels
-        let frame_count = buf.frames();
-        for frame_idx in 0..frame_count {
-            let mut sum = 0.0f32;
-            for ch in 0..channels {
-                let sample: f32 = buf.chan(ch)[frame_idx].into_sample();
-                sum += sample;
-            }
-            all_samples.push(sum / channels as f32);
+    // interleave planar channel data frame by frame
+    // packets with fewer channels than the track are padded with silence
+    let buf_channels = buf.spec().channels.count();
+    let frame_count = buf.frames();
+    all_samples.reserve(frame_count * channels);
+    for frame_idx in 0..frame_count {
+        for ch in 0..channels {
+            let sample: f32 = if ch < buf_channels {
+                buf.chan(ch)[frame_idx].into_sample()
+            } else {
+                0.0
+            };
+            all_samples.push(sample);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo_params(sample_rate: u32) -> CodecParameters {
+        let mut params = CodecParameters::new();
+        params
+            .with_sample_rate(sample_rate)
+            .with_channels(Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        params
+    }
+
+    #[test]
+    fn reset_keeps_unchanged_streams() {
+        assert!(check_unchanged(&stereo_params(44100), 44100.0, 2).is_ok());
+        // a track without parameters keeps the old ones
+        assert!(check_unchanged(&CodecParameters::new(), 44100.0, 2).is_ok());
+    }
+
+    #[test]
+    fn reset_rejects_a_new_rate_or_channel_count() {
+        assert!(matches!(
+            check_unchanged(&stereo_params(48000), 44100.0, 2),
+            Err(DecodeError::StreamChanged {
+                sample_rate: 48000.0,
+                channels: 2
+            })
+        ));
+        assert!(matches!(
+            check_unchanged(&stereo_params(44100), 44100.0, 1),
+            Err(DecodeError::StreamChanged {
+                sample_rate: 44100.0,
+                channels: 2
+            })
+        ));
+    }
+}
@@ -1,16 +1,57 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use std::cmp::Reverse;
-use std::collections::{BinaryHeap, VecDeque};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
-use std::time::Duration;
-
+use std::time::{Duration, Instant};
+
+use crate::backend::{OutputBackend, OutputConfig, OutputStream};
+use crate::channels::remix;
+use crate::chroma::Chroma;
+use crate::clock::PlaybackClock;
+use crate::key::Key;
+use crate::multipitch::PitchSalience;
+use crate::notes::{BandResult, Chord};
+use crate::resample::Resampler;
+use crate::ring::{RingConsumer, RingProducer, ring_buffer};
+use crate::stream::AudioChunk;
 use crate::visualizer::VisualizerData;
 
+// longest a seek waits for the callback to drop queued audio
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+// flush target while a seek hasn't landed yet
+const NO_TARGET: usize = usize::MAX;
+
 #[derive(Debug, Clone)]
 pub struct AnalysisResult {
     pub timestamp: Duration,
-    pub note: String,
+    /// one per frequency band, lowest first
+    pub bands: Vec<BandResult>,
+    /// notes sounding together, strongest first
+    pub pitches: Vec<PitchSalience>,
+    /// chord of `pitches`
+    pub chord: Option<Chord>,
+    /// pitch class profile of the whole spectrum
+    pub chroma: Chroma,
+    /// rolling key estimate up to this point
+    pub key: Option<Key>,
+    /// tempo in BPM, once there is enough audio to tell
+    pub tempo: Option<f32>,
+    /// beats found in the audio of this result
+    pub beats: Vec<Duration>,
+}
+
+impl fmt::Display for AnalysisResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, band) in self.bands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", band)?;
+        }
+        Ok(())
+    }
 }
 
 impl PartialEq for AnalysisResult {
@@ -34,19 +75,24 @@ impl Ord for AnalysisResult {
 }
 
 pub struct AudioOutput {
-    receiver: mpsc::Receiver<Vec<f32>>,
-    buffer: Arc<Mutex<VecDeque<f32>>>,
+    receiver: mpsc::Receiver<AudioChunk>,
+    // bumped to drop queued audio, e.g. after a seek
+    flush_requested: Arc<AtomicU64>,
+    // last flush the callback has carried out
+    flushed: Arc<AtomicU64>,
+    // frame the first chunk after the last flush starts at
+    flush_target: Arc<AtomicUsize>,
 
     // store analysis results
     analysis_results: Arc<Mutex<BinaryHeap<Reverse<AnalysisResult>>>>,
     analysis_receiver: mpsc::Receiver<AnalysisResult>,
 
-    // playback tracking
-    current_playback_time: Arc<Mutex<Duration>>,
+    // playback tracking, advanced by the frames the callback plays
+    clock: PlaybackClock,
     sample_rate: f32,
+    channels: usize,
     total_duration: Duration,
-    is_paused: Arc<Mutex<bool>>,
-    fade_samples: Arc<Mutex<usize>>,
+    is_paused: Arc<AtomicBool>,
 
     // visualizer
     visualizer_data: Arc<Mutex<VisualizerData>>,
@@ -54,134 +100,121 @@ pub struct AudioOutput {
 
 impl AudioOutput {
     pub fn new(
-        receiver: mpsc::Receiver<Vec<f32>>,
+        receiver: mpsc::Receiver<AudioChunk>,
         analysis_receiver: mpsc::Receiver<AnalysisResult>,
         visualizer_data: Arc<Mutex<VisualizerData>>,
+        clock: PlaybackClock,
         sample_rate: f32,
+        channels: usize,
         total_duration: Duration,
     ) -> Self {
         Self {
             receiver,
-            buffer: Arc::new(Mutex::new(VecDeque::with_capacity(88200))),
+            flush_requested: Arc::new(AtomicU64::new(0)),
+            flushed: Arc::new(AtomicU64::new(0)),
+            flush_target: Arc::new(AtomicUsize::new(NO_TARGET)),
             analysis_results: Arc::new(Mutex::new(BinaryHeap::new())),
             analysis_receiver,
-            current_playback_time: Arc::new(Mutex::new(Duration::ZERO)),
+            clock,
             sample_rate,
+            channels,
             total_duration,
             visualizer_data,
-            is_paused: Arc::new(Mutex::new(false)),
-            fade_samples: Arc::new(Mutex::new(0)),
+            is_paused: Arc::new(AtomicBool::new(false)),
         }
     }
 
     pub fn start_playback(
         &mut self,
-        sample_rate: f32,
-    ) -> Result<cpal::Stream, Box<dyn std::error::Error>> {
-        let host = cpal::default_host();
-        let device = host.default_output_device().expect("No output device");
-
-        let config = cpal::StreamConfig {
-            channels: 1,
-            sample_rate: cpal::SampleRate(sample_rate as u32),
-            buffer_size: cpal::BufferSize::Default,
-        };
+        backend: &dyn OutputBackend,
+    ) -> Result<OutputStream, Box<dyn std::error::Error>> {
+        // play at the output's rate and channel count, resampling and
+        // remixing the file to match
+        let config: OutputConfig = backend.default_config()?;
+        let output_sample_rate = config.sample_rate;
+        let resampler = Resampler::new(self.sample_rate, output_sample_rate as f32, self.channels);
+        self.clock.set_sample_rate(output_sample_rate as f32);
+
+        // 4 s of device audio, comfortably more than the streamer reads ahead
+        let sample_rate = output_sample_rate as f32;
+        let channels = config.channels;
+        let (producer, mut consumer) = ring_buffer(output_sample_rate as usize * channels * 4);
+        // played frames downmixed for the visualizer
+        let (mut vis_producer, vis_consumer) = ring_buffer(output_sample_rate as usize);
 
         // receive analysis
         self.start_analysis_collection();
 
         // start filling buffer
-        self.start_buffer_filling();
+        self.start_buffer_filling(producer, resampler, channels);
+
+        // show playback and analysis as the clock moves
+        self.start_display(vis_consumer);
 
         // sleep to let buffer fill
         thread::sleep(Duration::from_millis(200));
 
-        let playback_buffer = self.buffer.clone();
-        let analysis_results = self.analysis_results.clone();
-        let current_time = self.current_playback_time.clone();
-        let visualizer_data = self.visualizer_data.clone();
-        let sample_rate = self.sample_rate;
-        let total_duration = self.total_duration;
+        let clock = self.clock.clone();
         let is_paused = self.is_paused.clone();
+        let flush_requested = self.flush_requested.clone();
+        let flushed = self.flushed.clone();
 
         // 5 ms of fade
-        let fade_duration_samples = (sample_rate * 0.005) as usize;
-        let fade_samples = self.fade_samples.clone();
+        let fade_duration_samples = (sample_rate * 0.005) as usize * channels;
+        let mut fade_count = 0;
 
-        // build stream object
+        // start output
         // move ownership of cloned pointers to callback
-        // stream will periodically invoke callback per sample rate
-        let stream = device.build_output_stream(
-            &config,
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let paused = *is_paused.lock().unwrap();
-                let mut fade_count = fade_samples.lock().unwrap();
-
-                if paused {
-                    let mut buf = playback_buffer.lock().unwrap();
+        // output will periodically invoke callback per sample rate
+        // runs on the real-time thread, so no locks or allocations in here
+        let stream = backend.start(
+            config,
+            Box::new(move |data: &mut [f32], latency: Duration| {
+                // drop everything queued before a seek
+                let requested = flush_requested.load(Ordering::Acquire);
+                if flushed.load(Ordering::Relaxed) != requested {
+                    consumer.clear();
+                    flushed.store(requested, Ordering::Release);
+                }
 
-                    // output silence
+                let played = if is_paused.load(Ordering::Acquire) {
+                    // fade out, then output silence
+                    let mut played = 0;
                     for sample in data.iter_mut() {
-                        if *fade_count < fade_duration_samples {
-                            let audio_sample = buf.pop_front().unwrap_or(0.0);
+                        if fade_count < fade_duration_samples
+                            && let Some(audio_sample) = consumer.pop()
+                        {
                             let fade_multiplier =
-                                1.0 - (*fade_count as f32 / fade_duration_samples as f32);
+                                1.0 - (fade_count as f32 / fade_duration_samples as f32);
                             *sample = audio_sample * fade_multiplier;
-                            *fade_count += 2;
+                            fade_count += 1;
+                            played += 1;
                         } else {
                             *sample = 0.0;
                         }
                     }
-                    return;
-                }
-
-                // load samples from playback buffer into data vector of stream obj
-                let mut buf = playback_buffer.lock().unwrap();
-                let mut current_timestamp = current_time.lock().unwrap();
-
-                let mut frame_samples = Vec::with_capacity(data.len());
-
-                for sample in data.iter_mut() {
-                    let audio_sample = buf.pop_front().unwrap_or(0.0);
-                    *sample = audio_sample;
-
-                    // add frames for visualizer
-                    frame_samples.push(audio_sample);
-
-                    // update current playback time
-                    *current_timestamp += Duration::from_secs_f32(1.0 / sample_rate);
-                }
-
-                // update visualizer data
-                {
-                    let mut vis_data = visualizer_data.lock().unwrap();
-
-                    vis_data.current_time = *current_timestamp;
-                    vis_data.total_duration = total_duration;
-
-                    vis_data.amplitude_samples.extend(frame_samples);
-                    let vis_len = vis_data.amplitude_samples.len();
-                    if vis_len > 2048 {
-                        vis_data.amplitude_samples.drain(0..vis_len - 2048);
-                    }
-                }
-
-                Self::check_and_display_analysis(
-                    &analysis_results,
-                    *current_timestamp,
-                    &visualizer_data,
-                );
-
-                // warn
-                if buf.len() < 4410 {
-                    // println!("Audio buffer running low: {} samples", buf.len());
+                    played
+                } else {
+                    fade_count = 0;
+
+                    // load samples from playback buffer into data vector of stream obj
+                    let played = consumer.pop_slice(data);
+                    // underruns play silence without moving the clock
+                    data[played..].fill(0.0);
+                    played
+                };
+
+                // update current playback time
+                clock.advance(played / channels, latency);
+
+                // visualizer shows the downmixed signal, dropped if it falls behind
+                for frame in data[..played].chunks_exact(channels) {
+                    vis_producer.push(frame.iter().sum::<f32>() / channels as f32);
                 }
-            },
-            |err| eprintln!("Audio output error: {}", err),
-            None,
+                played
+            }),
         )?;
 
-        stream.play()?;
         Ok(stream)
     }
 
@@ -200,36 +233,135 @@ impl AudioOutput {
         });
     }
 
-    fn start_buffer_filling(&mut self) {
+    fn start_buffer_filling(
+        &mut self,
+        mut producer: RingProducer,
+        mut resampler: Resampler,
+        output_channels: usize,
+    ) {
         // move sync mpsc channel outbound into our receiver obj
-        let buffer = self.buffer.clone();
         let receiver = std::mem::replace(&mut self.receiver, mpsc::channel().1);
+        let source_channels = self.channels;
+        let flush_requested = self.flush_requested.clone();
+        let flushed = self.flushed.clone();
+        let flush_target = self.flush_target.clone();
 
         thread::spawn(move || {
-            while let Ok(chunk) = receiver.recv() {
-                let mut buf = buffer.lock().unwrap();
+            let mut resampled = Vec::new();
+            let mut remixed = Vec::new();
+            let mut epoch = flush_requested.load(Ordering::Acquire);
+            let mut skipping = false;
+
+            while let Ok((start, chunk)) = receiver.recv() {
+                // after a flush, start over once the callback has dropped the old audio
+                let requested = flush_requested.load(Ordering::Acquire);
+                if requested != epoch {
+                    resampler.reset();
+                    epoch = requested;
+                    skipping = true;
+
+                    // a stalled output never answers, so carry on after the
+                    // timeout like `clear_buffers` does
+                    let deadline = Instant::now() + FLUSH_TIMEOUT;
+                    while flushed.load(Ordering::Acquire) < epoch && Instant::now() < deadline {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                }
 
-                if buf.len() > 176400 {
-                    // println!("Audio buffer overflowed by {} samples", buf.len() - 176400);
+                // chunks read before the seek are still queued, drop them
+                // until the one starting where the source landed
+                if skipping {
+                    let target = loop {
+                        let target = flush_target.load(Ordering::Acquire);
+                        if target != NO_TARGET || flush_requested.load(Ordering::Acquire) != epoch {
+                            break target;
+                        }
+                        thread::sleep(Duration::from_millis(1));
+                    };
+                    if start != target {
+                        continue;
+                    }
+                    skipping = false;
+                }
+
+                resampled.clear();
+                if chunk.is_empty() {
+                    // end of stream
+                    resampler.flush(&mut resampled);
+                } else {
+                    resampler.process(&chunk, &mut resampled);
+                }
+                remixed.clear();
+                remix(&resampled, source_channels, output_channels, &mut remixed);
+
+                // the streamer keeps this bounded by watching the clock,
+                // so waiting for room is rare
+                let mut written = 0;
+                while written < remixed.len() {
+                    if flush_requested.load(Ordering::Acquire) != epoch {
+                        break;
+                    }
+                    written += producer.push_slice(&remixed[written..]);
+                    if written < remixed.len() {
+                        thread::sleep(Duration::from_millis(5));
+                    }
                 }
+            }
+        });
+    }
+
+    fn start_display(&self, mut vis_consumer: RingConsumer) {
+        let analysis_results = self.analysis_results.clone();
+        let visualizer_data = self.visualizer_data.clone();
+        let clock = self.clock.clone();
+        let total_duration = self.total_duration;
+
+        thread::spawn(move || {
+            let mut samples = vec![0.0; 2048];
+            loop {
+                let current_time = clock.now();
+
+                // update visualizer data
+                {
+                    let mut vis_data = visualizer_data.lock().unwrap();
 
-                for sample in chunk {
-                    buf.push_back(sample);
+                    vis_data.current_time = current_time;
+                    vis_data.total_duration = total_duration;
+
+                    while !vis_consumer.is_empty() {
+                        let count = vis_consumer.pop_slice(&mut samples);
+                        vis_data.amplitude_samples.extend(&samples[..count]);
+                    }
+                    let vis_len = vis_data.amplitude_samples.len();
+                    if vis_len > 2048 {
+                        vis_data.amplitude_samples.drain(0..vis_len - 2048);
+                    }
                 }
+
+                Self::check_and_display_analysis(&analysis_results, current_time, &visualizer_data);
+
+                thread::sleep(Duration::from_millis(16));
             }
         });
     }
 
-    pub fn update_current_playback_time(&self, new_time: Duration) {
-        let mut current_time = self.current_playback_time.lock().unwrap();
-        *current_time = new_time;
+    /// Drops all queued audio, then runs `seek`, which returns the frame
+    /// playback continues from. Waits until the callback has dropped the old
+    /// audio first, so old audio doesn't advance the moved clock.
+    pub fn seek(&self, seek: impl FnOnce() -> usize) {
+        self.clear_buffers();
+        let target = seek();
+        self.flush_target.store(target, Ordering::Release);
     }
 
-    pub fn clear_buffers(&self) {
-        // new scope to quickly mut playback buffer
-        {
-            let mut buf = self.buffer.lock().unwrap();
-            buf.clear();
+    fn clear_buffers(&self) {
+        self.flush_target.store(NO_TARGET, Ordering::Release);
+        // the callback drops queued audio on its next run
+        let requested = self.flush_requested.fetch_add(1, Ordering::AcqRel) + 1;
+        // a stalled output never answers, so don't wait on it forever
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        while self.flushed.load(Ordering::Acquire) < requested && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
         }
 
         // new scope to quickly mut results buffer
@@ -251,42 +383,15 @@ impl AudioOutput {
             if front_result.timestamp <= current_time {
                 let Reverse(result) = results.pop().unwrap();
 
-                {
-                    let mut vis_data = visualizer_data.lock().unwrap();
-                    vis_data.current_note = Some(result.note.clone());
-                    vis_data
-                        .note_history
-                        .push_back((result.timestamp, result.note));
-
-                    if vis_data.note_history.len() > 20 {
-                        vis_data.note_history.pop_front();
-                    }
-                }
-                // println!("🎵 [{:?}] {}", result.timestamp, result.note);
+                visualizer_data.lock().unwrap().apply(&result);
+                // println!("🎵 [{:?}] {}", result.timestamp, result);
             } else {
                 break; // Stop when we hit a future timestamp
             }
         }
     }
 
-    pub fn pause(&self) {
-        let mut paused = self.is_paused.lock().unwrap();
-        *paused = true;
-    }
-
-    pub fn resume(&self) {
-        let mut paused = self.is_paused.lock().unwrap();
-        let mut fade = self.fade_samples.lock().unwrap();
-        *paused = false;
-        *fade = 0;
-    }
-
     pub fn toggle(&self) {
-        let mut paused = self.is_paused.lock().unwrap();
-        *paused = !(*paused);
-        if !*paused {
-            let mut fade = self.fade_samples.lock().unwrap();
-            *fade = 0;
-        }
+        self.is_paused.fetch_xor(true, Ordering::AcqRel);
     }
 }
@@ -0,0 +1,341 @@
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{Device, FromSample, SampleFormat, SizedSample, StreamConfig};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::device::OutputSelection;
+
+/// Fills a buffer of interleaved frames and returns how many samples are
+/// real audio, the rest being silence from an underrun. The second argument
+/// is the delay until the first frame is heard.
+pub type RenderFn = Box<dyn FnMut(&mut [f32], Duration) -> usize + Send + 'static>;
+
+/// Sample rate and channel count an output runs at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputConfig {
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+/// Somewhere to send rendered audio: a sound device, nothing, or a file.
+pub trait OutputBackend: Send {
+    /// Format the backend runs at unless asked for something else.
+    fn default_config(&self) -> Result<OutputConfig, Box<dyn std::error::Error>>;
+
+    /// Starts pulling audio from `render` until the returned stream is dropped.
+    fn start(
+        &self,
+        config: OutputConfig,
+        render: RenderFn,
+    ) -> Result<OutputStream, Box<dyn std::error::Error>>;
+
+    /// True if nobody can hear the output, e.g. in CI.
+    fn is_headless(&self) -> bool;
+}
+
+/// Keeps an output running. Dropping it stops the output.
+pub struct OutputStream {
+    // device output
+    _stream: Option<cpal::Stream>,
+    // headless output runs on its own thread
+    stop: Arc<AtomicBool>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for OutputStream {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Plays on a sound card through cpal.
+pub struct DeviceBackend {
+    selection: OutputSelection,
+}
+
+impl DeviceBackend {
+    pub fn new(selection: OutputSelection) -> Self {
+        Self { selection }
+    }
+}
+
+impl OutputBackend for DeviceBackend {
+    fn default_config(&self) -> Result<OutputConfig, Box<dyn std::error::Error>> {
+        let config = self.selection.open()?.default_output_config()?;
+        Ok(OutputConfig {
+            sample_rate: config.sample_rate().0,
+            channels: config.channels() as usize,
+        })
+    }
+
+    fn start(
+        &self,
+        config: OutputConfig,
+        render: RenderFn,
+    ) -> Result<OutputStream, Box<dyn std::error::Error>> {
+        let device = self.selection.open()?;
+        let sample_format = device.default_output_config()?.sample_format();
+        let supported = device.supported_output_configs()?.any(|range| {
+            range.channels() as usize == config.channels
+                && (range.min_sample_rate().0..=range.max_sample_rate().0)
+                    .contains(&config.sample_rate)
+        });
+        if !supported {
+            return Err(format!(
+                "output device doesn't support {} channels at {} Hz",
+                config.channels, config.sample_rate
+            )
+            .into());
+        }
+        let stream_config = StreamConfig {
+            channels: config.channels as u16,
+            sample_rate: cpal::SampleRate(config.sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        // match the device's native sample format
+        let stream = match sample_format {
+            SampleFormat::F32 => build_device_stream::<f32>(&device, &stream_config, render),
+            SampleFormat::I16 => build_device_stream::<i16>(&device, &stream_config, render),
+            SampleFormat::U16 => build_device_stream::<u16>(&device, &stream_config, render),
+            format => return Err(format!("unsupported output sample format {}", format).into()),
+        }?;
+        stream.play()?;
+
+        Ok(OutputStream {
+            _stream: Some(stream),
+            stop: Arc::new(AtomicBool::new(false)),
+            worker: None,
+        })
+    }
+
+    fn is_headless(&self) -> bool {
+        false
+    }
+}
+
+fn build_device_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut render: RenderFn,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    // rendered in f32, then converted, only grows if the device asks for more
+    let mut scratch: Vec<f32> = Vec::with_capacity(16384);
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            // delay until the first frame of this buffer is heard
+            let timestamp = info.timestamp();
+            let latency = timestamp
+                .playback
+                .duration_since(&timestamp.callback)
+                .unwrap_or(Duration::ZERO);
+
+            scratch.resize(data.len(), 0.0);
+            render(&mut scratch, latency);
+            for (sample, value) in data.iter_mut().zip(&scratch) {
+                *sample = T::from_sample(*value);
+            }
+        },
+        |err| eprintln!("Audio output error: {}", err),
+        None,
+    )
+}
+
+// frames rendered per block by the headless backends
+const HEADLESS_BLOCK_FRAMES: usize = 512;
+
+/// Discards audio, either at real-time pace or as fast as it is rendered.
+/// Running as fast as possible skips underruns instead of waiting them out.
+pub struct NullBackend {
+    realtime: bool,
+}
+
+impl NullBackend {
+    pub fn new(realtime: bool) -> Self {
+        Self { realtime }
+    }
+}
+
+impl OutputBackend for NullBackend {
+    fn default_config(&self) -> Result<OutputConfig, Box<dyn std::error::Error>> {
+        Ok(HEADLESS_CONFIG)
+    }
+
+    fn start(
+        &self,
+        config: OutputConfig,
+        render: RenderFn,
+    ) -> Result<OutputStream, Box<dyn std::error::Error>> {
+        Ok(start_headless(config, self.realtime, render, |_| Ok(())))
+    }
+
+    fn is_headless(&self) -> bool {
+        true
+    }
+}
+
+/// Writes audio to a 16-bit WAV file instead of playing it.
+pub struct FileBackend {
+    path: PathBuf,
+    realtime: bool,
+}
+
+impl FileBackend {
+    pub fn new(path: PathBuf, realtime: bool) -> Self {
+        Self { path, realtime }
+    }
+}
+
+impl OutputBackend for FileBackend {
+    fn default_config(&self) -> Result<OutputConfig, Box<dyn std::error::Error>> {
+        Ok(HEADLESS_CONFIG)
+    }
+
+    fn start(
+        &self,
+        config: OutputConfig,
+        render: RenderFn,
+    ) -> Result<OutputStream, Box<dyn std::error::Error>> {
+        let mut writer = WavWriter::create(&self.path, config)?;
+        let path = self.path.clone();
+        Ok(start_headless(
+            config,
+            self.realtime,
+            render,
+            move |block| {
+                let result = match block {
+                    Some(samples) => writer.write(samples),
+                    None => writer.finish(),
+                };
+                result.map_err(|err| eprintln!("Failed to write {}: {}", path.display(), err))
+            },
+        ))
+    }
+
+    fn is_headless(&self) -> bool {
+        true
+    }
+}
+
+// what a typical sound card would run at
+const HEADLESS_CONFIG: OutputConfig = OutputConfig {
+    sample_rate: 48000,
+    channels: 2,
+};
+
+// renders blocks on a thread and hands them to `sink`, with `None` once stopped
+fn start_headless<F>(
+    config: OutputConfig,
+    realtime: bool,
+    mut render: RenderFn,
+    mut sink: F,
+) -> OutputStream
+where
+    F: FnMut(Option<&[f32]>) -> Result<(), ()> + Send + 'static,
+{
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = stop.clone();
+
+    let worker = thread::spawn(move || {
+        let mut block = vec![0.0; HEADLESS_BLOCK_FRAMES * config.channels];
+        let started = Instant::now();
+        let mut rendered_frames: u64 = 0;
+
+        while !stop_clone.load(Ordering::Acquire) {
+            let rendered = render(&mut block, Duration::ZERO);
+
+            // without real-time pacing there is no deadline to miss,
+            // so only real audio is passed on
+            let samples = if realtime {
+                &block[..]
+            } else {
+                &block[..rendered]
+            };
+            if sink(Some(samples)).is_err() {
+                break;
+            }
+            rendered_frames += (samples.len() / config.channels) as u64;
+
+            if realtime {
+                // a device only asks for the next block once this one played
+                let block_end =
+                    Duration::from_secs_f64(rendered_frames as f64 / config.sample_rate as f64);
+                if let Some(wait) = block_end.checked_sub(started.elapsed()) {
+                    thread::sleep(wait);
+                }
+            } else {
+                thread::yield_now();
+            }
+        }
+        let _ = sink(None);
+    });
+
+    OutputStream {
+        _stream: None,
+        stop,
+        worker: Some(worker),
+    }
+}
+
+// streams 16-bit pcm and fills in the header sizes when finished
+struct WavWriter {
+    writer: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+    fn create(path: &PathBuf, config: OutputConfig) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let channels = config.channels as u16;
+        let block_align = channels * 2;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // pcm
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&config.sample_rate.to_le_bytes())?;
+        writer.write_all(&(config.sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            data_bytes: 0,
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.data_bytes += (samples.len() * 2) as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_bytes.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::fft::{SpectrumAnalyzer, parabolic_offset};
+use crate::window::{StftConfig, WindowKind};
+
+// tempo range searched, and the tempo preferred when several fit
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+const PREFERRED_BPM: f32 = 120.0;
+// width of that preference in octaves
+const TEMPO_SPREAD: f32 = 1.0;
+// how strongly beats are kept at one period apart
+const TIGHTNESS: f32 = 100.0;
+
+// windows of the live onset detector, the hop sets the frame rate
+const LIVE_STFT: StftConfig = StftConfig::new(1024, WindowKind::Hann);
+// onset history the live estimate is based on, and the least it needs
+const LIVE_HISTORY: Duration = Duration::from_secs(8);
+const LIVE_MIN_HISTORY: Duration = Duration::from_secs(4);
+// chunks further than this from where the last one ended start over
+const LIVE_MAX_JUMP: Duration = Duration::from_millis(50);
+
+/// Spectral flux of consecutive magnitude spectra.
+#[derive(Default)]
+pub struct OnsetDetector {
+    previous: Option<Vec<f32>>,
+}
+
+impl OnsetDetector {
+    /// How much louder `magnitudes` got than the previous spectrum, summed
+    /// over rising bins only. The first spectrum has no onset.
+    pub fn strength(&mut self, magnitudes: &[f32]) -> f32 {
+        let current: Vec<f32> = magnitudes
+            .iter()
+            .map(|magnitude| (1.0 + magnitude).ln())
+            .collect();
+        let flux = match &self.previous {
+            Some(previous) if previous.len() == current.len() => current
+                .iter()
+                .zip(previous)
+                .map(|(now, before)| (now - before).max(0.0))
+                .sum(),
+            _ => 0.0,
+        };
+        self.previous = Some(current);
+        flux
+    }
+}
+
+/// Tempo in BPM from the autocorrelation of an onset envelope sampled at
+/// `frame_rate`, or `None` if it has no periodicity.
+pub fn estimate_tempo(onsets: &[f32], frame_rate: f32) -> Option<f32> {
+    let envelope = normalize(onsets);
+    let min_lag = (frame_rate * 60.0 / MAX_BPM).floor().max(1.0) as usize;
+    let max_lag = (frame_rate * 60.0 / MIN_BPM).ceil() as usize;
+    if envelope.len() <= max_lag + 1 {
+        return None;
+    }
+
+    let autocorrelation = |lag: usize| -> f32 {
+        envelope
+            .iter()
+            .zip(&envelope[lag..])
+            .map(|(a, b)| a * b)
+            .sum::<f32>()
+            / (envelope.len() - lag) as f32
+    };
+
+    let (mut best_lag, mut best_score) = (0, 0.0);
+    for lag in min_lag..=max_lag {
+        let bpm = 60.0 * frame_rate / lag as f32;
+        // favour tempos near the preferred one over their multiples
+        let octaves = (bpm / PREFERRED_BPM).log2() / TEMPO_SPREAD;
+        let score = autocorrelation(lag) * (-0.5 * octaves * octaves).exp();
+        if score > best_score {
+            (best_lag, best_score) = (lag, score);
+        }
+    }
+    if best_lag == 0 {
+        return None;
+    }
+
+    // refine the lag between frames with a parabola through its neighbours
+    let around = [best_lag - 1, best_lag, best_lag + 1].map(autocorrelation);
+    let offset = parabolic_offset(&around, 1);
+    Some(60.0 * frame_rate / (best_lag as f32 + offset))
+}
+
+/// Frames of an onset envelope that are beats at `bpm`. Dynamic
+/// programming picks the beats that land on strong onsets while staying
+/// about one period apart.
+pub fn track_beats(onsets: &[f32], frame_rate: f32, bpm: f32) -> Vec<usize> {
+    let envelope = normalize(onsets);
+    let period = frame_rate * 60.0 / bpm;
+    if envelope.is_empty() || period < 1.0 {
+        return Vec::new();
+    }
+
+    // best score of a beat sequence ending on each frame, and the beat before
+    let mut scores = envelope.clone();
+    let mut previous: Vec<Option<usize>> = vec![None; envelope.len()];
+    for frame in 0..envelope.len() {
+        let earliest = frame.saturating_sub((2.0 * period).round() as usize);
+        let latest = frame.saturating_sub((period / 2.0).round() as usize);
+        if latest == frame {
+            continue;
+        }
+
+        let mut best: Option<(usize, f32)> = None;
+        for (candidate, &candidate_score) in
+            scores.iter().enumerate().take(latest + 1).skip(earliest)
+        {
+            let interval = (frame - candidate) as f32 / period;
+            let score = candidate_score - TIGHTNESS * interval.ln().powi(2);
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((candidate, score));
+            }
+        }
+        // a sequence that only loses score is better started over here
+        if let Some((candidate, score)) = best
+            && score > 0.0
+        {
+            scores[frame] += score;
+            previous[frame] = Some(candidate);
+        }
+    }
+
+    // the sequence ends on the best frame of the last period
+    let tail = envelope.len().saturating_sub(period.round() as usize);
+    let Some(mut frame) = (tail..envelope.len()).max_by(|a, b| scores[*a].total_cmp(&scores[*b]))
+    else {
+        return Vec::new();
+    };
+
+    let mut beats = vec![frame];
+    while let Some(before) = previous[frame] {
+        beats.push(before);
+        frame = before;
+    }
+    beats.reverse();
+    beats
+}
+
+/// Tempo and beats of live audio, estimated over the last few seconds.
+pub struct BeatTracker {
+    sample_rate: f32,
+    onsets: OnsetDetector,
+    spectrum: SpectrumAnalyzer,
+    // samples not yet covered by a full window, and the time of the first
+    pending: Vec<f32>,
+    pending_start: Duration,
+    // onset strengths, the first at `envelope_start`
+    envelope: VecDeque<f32>,
+    envelope_start: Duration,
+    last_beat: Option<Duration>,
+}
+
+impl BeatTracker {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            onsets: OnsetDetector::default(),
+            spectrum: SpectrumAnalyzer::from_stft(&LIVE_STFT),
+            pending: Vec::new(),
+            pending_start: Duration::ZERO,
+            envelope: VecDeque::new(),
+            envelope_start: Duration::ZERO,
+            last_beat: None,
+        }
+    }
+
+    /// Adds a chunk of mono samples starting at `timestamp` and returns the
+    /// tempo and the beats found in this chunk.
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+        timestamp: Duration,
+    ) -> (Option<f32>, Vec<Duration>) {
+        let hop = LIVE_STFT.hop_size;
+        let frame_rate = LIVE_STFT.frame_rate(self.sample_rate);
+
+        // seeks and dropouts break the envelope
+        let expected = self.pending_start + self.samples_to_time(self.pending.len());
+        if (self.envelope.is_empty() && self.pending.is_empty())
+            || timestamp.abs_diff(expected) > LIVE_MAX_JUMP
+        {
+            self.onsets = OnsetDetector::default();
+            self.pending.clear();
+            self.pending_start = timestamp;
+            self.envelope.clear();
+            self.envelope_start = timestamp;
+            self.last_beat = None;
+        }
+        self.pending.extend_from_slice(samples);
+
+        let mut consumed = 0;
+        for position in LIVE_STFT.window_starts(self.pending.len()) {
+            let window = &self.pending[position..position + LIVE_STFT.window_size];
+            if self.spectrum.process(window).is_err() {
+                break;
+            }
+            consumed = position + hop;
+            let strength = self.onsets.strength(self.spectrum.magnitudes());
+            self.envelope.push_back(strength);
+        }
+        self.pending.drain(..consumed);
+        self.pending_start += self.samples_to_time(consumed);
+
+        let max_frames = (LIVE_HISTORY.as_secs_f32() * frame_rate) as usize;
+        while self.envelope.len() > max_frames {
+            self.envelope.pop_front();
+            self.envelope_start += self.samples_to_time(hop);
+        }
+
+        let history = Duration::from_secs_f32(self.envelope.len() as f32 / frame_rate);
+        if history < LIVE_MIN_HISTORY {
+            return (None, Vec::new());
+        }
+
+        let envelope: Vec<f32> = self.envelope.iter().copied().collect();
+        let Some(bpm) = estimate_tempo(&envelope, frame_rate) else {
+            return (None, Vec::new());
+        };
+        let min_gap = Duration::from_secs_f32(30.0 / bpm);
+
+        // only beats in this chunk, at least half a period after the last one
+        let mut beats = Vec::new();
+        for frame in track_beats(&envelope, frame_rate, bpm) {
+            // an onset peaks when it reaches the middle of the window
+            let time =
+                self.envelope_start + self.samples_to_time(frame * hop + LIVE_STFT.window_size / 2);
+            if time < timestamp || self.last_beat.is_some_and(|last| time < last + min_gap) {
+                continue;
+            }
+            beats.push(time);
+            self.last_beat = Some(time);
+        }
+        (Some(bpm), beats)
+    }
+
+    fn samples_to_time(&self, samples: usize) -> Duration {
+        Duration::from_secs_f64(samples as f64 / self.sample_rate as f64)
+    }
+}
+
+// zero mean and unit deviation, so scores don't depend on loudness
+fn normalize(onsets: &[f32]) -> Vec<f32> {
+    if onsets.is_empty() {
+        return Vec::new();
+    }
+    let mean = onsets.iter().sum::<f32>() / onsets.len() as f32;
+    let deviation =
+        (onsets.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / onsets.len() as f32).sqrt();
+    if deviation <= f32::EPSILON {
+        return vec![0.0; onsets.len()];
+    }
+    onsets.iter().map(|x| (x - mean) / deviation).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // onset envelope with an impulse every `period` seconds from 0.25 s on
+    fn impulses(frame_rate: f32, period: f32, seconds: f32) -> (Vec<f32>, Vec<usize>) {
+        let mut onsets = vec![0.0; (seconds * frame_rate) as usize];
+        let mut frames = Vec::new();
+        let mut time = 0.25;
+        while time < seconds {
+            let frame = (time * frame_rate).round() as usize;
+            if frame < onsets.len() {
+                onsets[frame] = 1.0;
+                frames.push(frame);
+            }
+            time += period;
+        }
+        (onsets, frames)
+    }
+
+    #[test]
+    fn finds_120_bpm_from_half_second_onsets() {
+        let frame_rate = LIVE_STFT.frame_rate(44100.0);
+        let (onsets, _) = impulses(frame_rate, 0.5, 10.0);
+        let bpm = estimate_tempo(&onsets, frame_rate).unwrap();
+        assert!((bpm - 120.0).abs() <= 1.0, "{} BPM", bpm);
+    }
+
+    #[test]
+    fn places_beats_on_the_onsets() {
+        let frame_rate = LIVE_STFT.frame_rate(44100.0);
+        let (onsets, expected) = impulses(frame_rate, 0.5, 10.0);
+        let bpm = estimate_tempo(&onsets, frame_rate).unwrap();
+
+        let beats = track_beats(&onsets, frame_rate, bpm);
+        assert_eq!(beats.len(), expected.len());
+        for (beat, onset) in beats.iter().zip(&expected) {
+            // within one hop
+            assert!(
+                beat.abs_diff(*onset) <= 1,
+                "beat {} for onset {}",
+                beat,
+                onset
+            );
+        }
+    }
+
+    #[test]
+    fn flat_envelope_has_no_tempo() {
+        assert_eq!(estimate_tempo(&[1.0; 2000], 100.0), None);
+        assert_eq!(estimate_tempo(&[0.0; 10], 100.0), None);
+    }
+}
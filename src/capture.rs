@@ -0,0 +1,313 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, FromSample, Sample, SampleFormat, SizedSample, StreamConfig};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::aux::AnalysisResult;
+use crate::channels::downmix;
+use crate::ring::{RingConsumer, RingProducer, ring_buffer};
+use crate::source::{AudioSource, SourceError};
+use crate::stream::AnalysisChunk;
+use crate::visualizer::VisualizerData;
+
+// seconds of input held for the reader, more than that is dropped
+const CAPTURE_BUFFER_SECONDS: usize = 2;
+
+/// Keeps a running capture alive. Dropping it stops a device capture.
+pub struct CaptureStream {
+    // none for fake input fed from a source
+    _stream: Option<cpal::Stream>,
+}
+
+/// Live input as an `AudioSource`. Reads block until enough frames have
+/// been captured, so consumers are paced by the input itself.
+pub struct CaptureSource {
+    consumer: RingConsumer,
+    // set once no more input will arrive
+    finished: Arc<AtomicBool>,
+    // blocks that didn't fit in the ring, and how many have been reported
+    dropped: Arc<AtomicUsize>,
+    reported_dropped: usize,
+    sample_rate: f32,
+    channels: usize,
+    name: String,
+
+    // captured samples not read yet
+    pending: Vec<f32>,
+    position: usize,
+}
+
+// writing end of a capture, owned by the input callback or fake device
+struct CaptureInput {
+    producer: RingProducer,
+    finished: Arc<AtomicBool>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl CaptureInput {
+    // the reader fell behind, so the block was cut short
+    fn count_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for CaptureInput {
+    fn drop(&mut self) {
+        self.finished.store(true, Ordering::Release);
+    }
+}
+
+fn capture_ring(sample_rate: f32, channels: usize, name: String) -> (CaptureInput, CaptureSource) {
+    let capacity = sample_rate as usize * channels * CAPTURE_BUFFER_SECONDS;
+    let (producer, consumer) = ring_buffer(capacity);
+    let finished = Arc::new(AtomicBool::new(false));
+    let dropped = Arc::new(AtomicUsize::new(0));
+    (
+        CaptureInput {
+            producer,
+            finished: finished.clone(),
+            dropped: dropped.clone(),
+        },
+        CaptureSource {
+            consumer,
+            finished,
+            dropped,
+            reported_dropped: 0,
+            sample_rate,
+            channels,
+            name,
+            pending: Vec::new(),
+            position: 0,
+        },
+    )
+}
+
+impl AudioSource for CaptureSource {
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, max_frames: usize) -> Result<Vec<f32>, SourceError> {
+        let max_samples = max_frames * self.channels;
+        let mut buffer = [0.0; 1024];
+        while self.pending.len() < max_samples {
+            // checked before draining so nothing written last is missed
+            let finished = self.finished.load(Ordering::Acquire);
+            let mut count = self.consumer.pop_slice(&mut buffer);
+            while count > 0 {
+                self.pending.extend_from_slice(&buffer[..count]);
+                count = self.consumer.pop_slice(&mut buffer);
+            }
+            if self.pending.len() >= max_samples {
+                break;
+            }
+            // capture stopped, hand out what is left
+            if finished {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > self.reported_dropped {
+            eprintln!("Input overrun, {} blocks dropped so far", dropped);
+            self.reported_dropped = dropped;
+        }
+
+        let count = max_samples.min(self.pending.len());
+        let chunk: Vec<f32> = self.pending.drain(..count).collect();
+        self.position += chunk.len() / self.channels;
+        Ok(chunk)
+    }
+
+    fn seek(&mut self, _time: Duration) -> Result<usize, SourceError> {
+        Err("cannot seek live input".into())
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(format!("Live: {}", self.name))
+    }
+}
+
+/// Captures from the default input device, e.g. a microphone or line-in.
+pub fn start_device_capture(
+    block_ms: u64,
+) -> Result<(CaptureStream, CaptureSource), Box<dyn std::error::Error>> {
+    let host = cpal::default_host();
+    let device = host
+        .default_input_device()
+        .ok_or("failed to find a default input device")?;
+    let supported_config = device.default_input_config()?;
+
+    let sample_format = supported_config.sample_format();
+    let config: StreamConfig = supported_config.into();
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let block_frames = ((sample_rate * block_ms as f32) / 1000.0) as usize;
+
+    let name = device.name().unwrap_or_else(|_| "input device".to_string());
+    let (input, source) = capture_ring(sample_rate, channels, name);
+
+    // match the device's native sample format
+    let stream = match sample_format {
+        SampleFormat::F32 => build_capture::<f32>(&device, &config, block_frames, input),
+        SampleFormat::I16 => build_capture::<i16>(&device, &config, block_frames, input),
+        SampleFormat::U16 => build_capture::<u16>(&device, &config, block_frames, input),
+        format => return Err(format!("unsupported input sample format {}", format).into()),
+    }?;
+    stream.play()?;
+
+    Ok((
+        CaptureStream {
+            _stream: Some(stream),
+        },
+        source,
+    ))
+}
+
+fn build_capture<T>(
+    device: &Device,
+    config: &StreamConfig,
+    block_frames: usize,
+    mut input: CaptureInput,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    // converted samples on their way into the ring
+    let mut converted = vec![0.0; (block_frames * channels).max(1)];
+
+    // runs on the real-time thread, so no locks, allocations or printing
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let mut overrun = false;
+            for samples in data.chunks(converted.len()) {
+                let converted = &mut converted[..samples.len()];
+                for (out, &sample) in converted.iter_mut().zip(samples) {
+                    *out = f32::from_sample(sample);
+                }
+                overrun |= input.producer.push_slice(converted) < converted.len();
+            }
+            if overrun {
+                input.count_dropped();
+            }
+        },
+        |err| eprintln!("Audio input error: {}", err),
+        None,
+    )
+}
+
+/// Stands in for an input device by playing any source into a capture
+/// at real-time pace, e.g. a decoded file or a test signal.
+pub fn start_fake_capture(
+    mut source: Box<dyn AudioSource>,
+    block_ms: u64,
+) -> (CaptureStream, CaptureSource) {
+    let sample_rate = source.sample_rate();
+    let channels = source.channels();
+    let name = source.name().unwrap_or_else(|| "fake input".to_string());
+    let block_frames = ((sample_rate * block_ms as f32) / 1000.0) as usize;
+
+    let (mut input, capture) = capture_ring(sample_rate, channels, name);
+    thread::spawn(move || {
+        let started = Instant::now();
+        let mut captured_frames: usize = 0;
+
+        loop {
+            let block = match source.read(block_frames) {
+                Ok(block) if !block.is_empty() => block,
+                Ok(_) => break,
+                Err(err) => {
+                    eprintln!("Stopped capture: {}", err);
+                    break;
+                }
+            };
+
+            captured_frames += block.len() / channels;
+
+            // a real device delivers a block only once it has been recorded
+            let block_end = Duration::from_secs_f32(captured_frames as f32 / sample_rate);
+            if let Some(wait) = block_end.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+
+            if input.producer.push_slice(&block) < block.len() {
+                input.count_dropped();
+            }
+        }
+    });
+
+    (CaptureStream { _stream: None }, capture)
+}
+
+/// Drives the visualizer from a source without playing it back.
+/// Blocks are shown as they are read and passed on to the analyzer,
+/// and analysis results are shown as soon as they are ready.
+pub fn start_live_monitor(
+    mut source: Box<dyn AudioSource>,
+    block_ms: u64,
+    analysis_receiver: mpsc::Receiver<AnalysisResult>,
+    visualizer_data: Arc<Mutex<VisualizerData>>,
+) -> mpsc::Receiver<AnalysisChunk> {
+    let (analysis_sender, forwarded_receiver) = mpsc::channel();
+    let sample_rate = source.sample_rate();
+    let channels = source.channels();
+    let block_frames = ((sample_rate * block_ms as f32) / 1000.0) as usize;
+
+    let vis_data = visualizer_data.clone();
+    thread::spawn(move || {
+        loop {
+            let start_pos = source.position();
+            let block = match source.read(block_frames) {
+                Ok(block) if !block.is_empty() => block,
+                Ok(_) => break,
+                Err(err) => {
+                    eprintln!("Stopped monitoring: {}", err);
+                    break;
+                }
+            };
+            let timestamp = Duration::from_secs_f32(start_pos as f32 / sample_rate);
+
+            {
+                let mut vis_data = vis_data.lock().unwrap();
+                vis_data.current_time =
+                    Duration::from_secs_f32(source.position() as f32 / sample_rate);
+
+                vis_data.amplitude_samples.extend(downmix(&block, channels));
+                let vis_len = vis_data.amplitude_samples.len();
+                if vis_len > 2048 {
+                    vis_data.amplitude_samples.drain(0..vis_len - 2048);
+                }
+            }
+
+            if analysis_sender.send((timestamp, block)).is_err() {
+                break;
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        while let Ok(result) = analysis_receiver.recv() {
+            visualizer_data.lock().unwrap().apply(&result);
+        }
+    });
+
+    forwarded_receiver
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Which signal the analyzer should look at when the source has
+/// more than one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    /// average of all channels
+    #[default]
+    Downmix,
+    /// a single channel by index
+    Channel(usize),
+    /// (left + right) / 2
+    Mid,
+    /// (left - right) / 2
+    Side,
+}
+
+impl ChannelMode {
+    /// Checks that a selected channel exists in a source with `channels` channels.
+    pub fn validate(&self, channels: usize) -> Result<(), String> {
+        match *self {
+            ChannelMode::Channel(channel) if channel >= channels => Err(format!(
+                "channel {} out of range, source has {}",
+                channel, channels
+            )),
+            ChannelMode::Mid | ChannelMode::Side if channels < 2 => {
+                Err(format!("{} needs a stereo source", self))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Extracts a mono signal from interleaved samples.
+    pub fn extract(&self, interleaved: &[f32], channels: usize) -> Vec<f32> {
+        if channels <= 1 {
+            return interleaved.to_vec();
+        }
+
+        let frames = interleaved.chunks_exact(channels);
+        match *self {
+            ChannelMode::Downmix => frames
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect(),
+            ChannelMode::Channel(channel) => {
+                let channel = channel.min(channels - 1);
+                frames.map(|frame| frame[channel]).collect()
+            }
+            ChannelMode::Mid => frames.map(|frame| (frame[0] + frame[1]) * 0.5).collect(),
+            ChannelMode::Side => frames.map(|frame| (frame[0] - frame[1]) * 0.5).collect(),
+        }
+    }
+}
+
+/// Downmixes interleaved samples to mono.
+pub fn downmix(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    ChannelMode::Downmix.extract(interleaved, channels)
+}
+
+/// Converts interleaved frames from `from` to `to` channels, appending them
+/// to `output`. Mono goes to every channel and anything becomes mono by
+/// averaging. Otherwise channels both sides have pass through, outputs the
+/// input lacks stay silent and inputs the output lacks are mixed into every
+/// output at half level.
+pub fn remix(interleaved: &[f32], from: usize, to: usize, output: &mut Vec<f32>) {
+    if from == to {
+        output.extend_from_slice(interleaved);
+        return;
+    }
+    output.reserve(interleaved.len() / from * to);
+    for frame in interleaved.chunks_exact(from) {
+        if from == 1 {
+            output.extend(std::iter::repeat_n(frame[0], to));
+        } else if to == 1 {
+            output.push(frame.iter().sum::<f32>() / from as f32);
+        } else {
+            let extra = if from > to {
+                0.5 * frame[to..].iter().sum::<f32>() / (from - to) as f32
+            } else {
+                0.0
+            };
+            output.extend((0..to).map(|channel| frame.get(channel).map_or(0.0, |s| s + extra)));
+        }
+    }
+}
+
+impl FromStr for ChannelMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mix" | "downmix" | "mono" => Ok(ChannelMode::Downmix),
+            "left" | "l" => Ok(ChannelMode::Channel(0)),
+            "right" | "r" => Ok(ChannelMode::Channel(1)),
+            "mid" | "m" => Ok(ChannelMode::Mid),
+            "side" | "s" => Ok(ChannelMode::Side),
+            other => other
+                .parse::<usize>()
+                .map(ChannelMode::Channel)
+                .map_err(|_| {
+                    format!(
+                        "unknown channel '{}', expected mix, left, right, mid, side or an index",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+impl fmt::Display for ChannelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelMode::Downmix => write!(f, "mix"),
+            ChannelMode::Channel(channel) => write!(f, "ch{}", channel),
+            ChannelMode::Mid => write!(f, "mid"),
+            ChannelMode::Side => write!(f, "side"),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::f32::consts::PI;
+
+use crate::fft::interpolate_peak;
+use crate::notes::pitch_class_name;
+
+/// Energy per pitch class, index 0 being C.
+pub type Chroma = [f32; 12];
+
+// range of fundamentals that count towards the chroma
+const MIN_FREQUENCY: f32 = 55.0;
+const MAX_FREQUENCY: f32 = 5000.0;
+// harmonics summed into each candidate fundamental, and the weight of each
+// one relative to the previous
+const HARMONICS: usize = 4;
+const HARMONIC_DECAY: f32 = 0.6;
+// peaks quieter than this fraction of the loudest one are ignored
+const PEAK_THRESHOLD: f32 = 0.01;
+// a peak within this fraction of a harmonic of a stronger lower peak is
+// mostly that peak's overtone, and only counts this much as a fundamental
+const HARMONIC_TOLERANCE: f32 = 0.03;
+const OVERTONE_WEIGHT: f32 = 0.2;
+
+/// 12-bin chroma of one window's magnitude spectrum, scaled so the
+/// strongest pitch class is 1. Each spectral peak is scored with its
+/// harmonics, peaks that are overtones of a stronger lower one count
+/// little, and pitch classes are placed relative to the estimated tuning
+/// instead of exactly A4 = 440Hz.
+pub fn spectrum_chroma(magnitudes: &[f32], sample_rate: f32, fft_size: usize) -> Chroma {
+    let mut chroma = [0.0; 12];
+    let bin_hz = sample_rate / fft_size as f32;
+    let peaks = spectral_peaks(magnitudes, bin_hz);
+    if peaks.is_empty() {
+        return chroma;
+    }
+    let tuning = estimate_tuning(&peaks);
+    let max_magnitude = magnitudes.iter().cloned().fold(0.0, f32::max);
+
+    for (i, &(frequency, magnitude)) in peaks.iter().enumerate() {
+        if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+            continue;
+        }
+        let overtone = peaks[..i].iter().any(|&(lower, lower_magnitude)| {
+            lower_magnitude > magnitude && is_harmonic(frequency, lower)
+        });
+
+        // a fundamental is only as strong as its harmonics say
+        let mut salience = 0.0;
+        let mut weight = 1.0;
+        for harmonic in 1..=HARMONICS {
+            let bin = (frequency * harmonic as f32 / bin_hz).round() as usize;
+            let Some(&magnitude) = magnitudes.get(bin) else {
+                break;
+            };
+            salience += weight * compress(magnitude / max_magnitude);
+            weight *= HARMONIC_DECAY;
+        }
+
+        if overtone {
+            salience *= OVERTONE_WEIGHT;
+        }
+
+        let midi_note = 69.0 + 12.0 * (frequency / 440.0).log2() - tuning / 100.0;
+        let pitch_class = (midi_note.round() as i32).rem_euclid(12) as usize;
+        chroma[pitch_class] += salience;
+    }
+
+    let max = chroma.iter().cloned().fold(0.0, f32::max);
+    if max > 0.0 {
+        for value in chroma.iter_mut() {
+            *value /= max;
+        }
+    }
+    chroma
+}
+
+/// Offset in cents, -50 to +50, of the spectrum from A4 = 440Hz.
+pub fn estimate_tuning(peaks: &[(f32, f32)]) -> f32 {
+    // deviations wrap around at a semitone, so they are averaged as angles
+    let (mut sin, mut cos) = (0.0, 0.0);
+    for &(frequency, magnitude) in peaks {
+        let midi_note = 69.0 + 12.0 * (frequency / 440.0).log2();
+        let angle = 2.0 * PI * (midi_note - midi_note.round());
+        sin += magnitude * angle.sin();
+        cos += magnitude * angle.cos();
+    }
+    if sin == 0.0 && cos == 0.0 {
+        return 0.0;
+    }
+    sin.atan2(cos) / (2.0 * PI) * 100.0
+}
+
+/// One line with a bar per pitch class, e.g. `C█ C#▁ D▃ ...`.
+pub fn chroma_bars(chroma: &Chroma) -> String {
+    const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    chroma
+        .iter()
+        .enumerate()
+        .map(|(pitch_class, value)| {
+            let level = (value.clamp(0.0, 1.0) * 8.0).round() as usize;
+            format!("{}{}", pitch_class_name(pitch_class as u8), BARS[level])
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// local maxima above the threshold as (frequency, magnitude), placed
+// between bins like the analyzer's peaks
+fn spectral_peaks(magnitudes: &[f32], bin_hz: f32) -> Vec<(f32, f32)> {
+    let max = magnitudes.iter().cloned().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return Vec::new();
+    }
+    let threshold = max * PEAK_THRESHOLD;
+
+    let mut peaks = Vec::new();
+    for bin in 1..magnitudes.len().saturating_sub(1) {
+        let (left, center, right) = (magnitudes[bin - 1], magnitudes[bin], magnitudes[bin + 1]);
+        if center < threshold || center <= left || center < right {
+            continue;
+        }
+
+        let (offset, _) = interpolate_peak(magnitudes, bin);
+        peaks.push(((bin as f32 + offset) * bin_hz, center));
+    }
+    peaks
+}
+
+// whether `frequency` is the second to HARMONICS-th harmonic of `fundamental`
+fn is_harmonic(frequency: f32, fundamental: f32) -> bool {
+    let harmonic = (frequency / fundamental).round();
+    (2.0..=HARMONICS as f32).contains(&harmonic)
+        && (frequency / (fundamental * harmonic) - 1.0).abs() <= HARMONIC_TOLERANCE
+}
+
+// log compression of a magnitude relative to the loudest bin, so loud
+// partials don't drown out the rest
+fn compress(magnitude: f32) -> f32 {
+    (1.0 + 100.0 * magnitude).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::SpectrumAnalyzer;
+    use crate::window::{StftConfig, WindowKind};
+
+    const SAMPLE_RATE: f32 = 44100.0;
+    const FFT_SIZE: usize = 8192;
+
+    // magnitudes of one window of a sine, through a window low enough in
+    // sidelobes that only the main peak stands out
+    fn sine_spectrum(frequency: f32) -> Vec<f32> {
+        let stft = StftConfig::new(FFT_SIZE, WindowKind::BlackmanHarris);
+        let mut analyzer = SpectrumAnalyzer::from_stft(&stft);
+        let samples: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| (2.0 * PI * frequency * i as f32 / SAMPLE_RATE).sin())
+            .collect();
+        analyzer.process(&samples).unwrap();
+        analyzer.magnitudes().to_vec()
+    }
+
+    fn cents_sharp(frequency: f32, cents: f32) -> f32 {
+        frequency * 2f32.powf(cents / 1200.0)
+    }
+
+    #[test]
+    fn puts_a_440_hz_tone_in_a() {
+        let chroma = spectrum_chroma(&sine_spectrum(440.0), SAMPLE_RATE, FFT_SIZE);
+        assert_eq!(chroma[9], 1.0);
+        for (pitch_class, value) in chroma.iter().enumerate() {
+            if pitch_class != 9 {
+                assert_eq!(*value, 0.0, "{}", pitch_class_name(pitch_class as u8));
+            }
+        }
+    }
+
+    #[test]
+    fn estimates_the_tuning_of_a_sharp_tone() {
+        assert!((estimate_tuning(&[(cents_sharp(440.0, 20.0), 1.0)]) - 20.0).abs() < 0.01);
+        assert!((estimate_tuning(&[(cents_sharp(261.63, -30.0), 1.0)]) + 30.0).abs() < 0.1);
+
+        let bin_hz = SAMPLE_RATE / FFT_SIZE as f32;
+        let peaks = spectral_peaks(&sine_spectrum(cents_sharp(440.0, 20.0)), bin_hz);
+        let tuning = estimate_tuning(&peaks);
+        assert!((tuning - 20.0).abs() < 2.0, "{} cents", tuning);
+    }
+
+    #[test]
+    fn keeps_a_sharp_tone_in_its_pitch_class() {
+        // 45 cents sharp is nearly A#, but the whole spectrum is that sharp
+        let spectrum = sine_spectrum(cents_sharp(440.0, 45.0));
+        let chroma = spectrum_chroma(&spectrum, SAMPLE_RATE, FFT_SIZE);
+        assert_eq!(chroma[9], 1.0);
+        assert_eq!(chroma[10], 0.0);
+    }
+}
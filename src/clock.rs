@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Playback position driven by the output callback.
+///
+/// The callback reports every frame it hands to the device, so the clock
+/// only moves when audio is actually played. Between callbacks the
+/// position is interpolated from the last one and the device latency.
+///
+/// `advance` only touches atomics and is safe to call from the real-time
+/// audio thread; everything else may lock.
+#[derive(Clone)]
+pub struct PlaybackClock {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    // reference point for callback times
+    epoch: Instant,
+
+    // written by the callback only
+    // frames consumed in total, and before the last callback
+    frames_after: AtomicU64,
+    frames_before: AtomicU64,
+    // nanos since `epoch` of the last callback, 0 before the first one
+    last_callback: AtomicU64,
+    // nanos until the first frame of the last callback is heard
+    latency: AtomicU64,
+
+    anchor: Mutex<Anchor>,
+}
+
+// maps the callback's frame counter to source time
+struct Anchor {
+    // source time at `frames`, moved on seek
+    time: Duration,
+    frames: u64,
+    // rate of the frames passed to `advance`
+    sample_rate: f32,
+}
+
+impl Anchor {
+    fn frames_to_time(&self, frames: u64) -> Duration {
+        let frames = frames.saturating_sub(self.frames);
+        self.time + Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+impl PlaybackClock {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                epoch: Instant::now(),
+                frames_after: AtomicU64::new(0),
+                frames_before: AtomicU64::new(0),
+                last_callback: AtomicU64::new(0),
+                latency: AtomicU64::new(0),
+                anchor: Mutex::new(Anchor {
+                    time: Duration::ZERO,
+                    frames: 0,
+                    sample_rate,
+                }),
+            }),
+        }
+    }
+
+    /// Sets the rate of the frames passed to `advance`, e.g. the device rate.
+    pub fn set_sample_rate(&self, sample_rate: f32) {
+        // keep the current position when the rate changes
+        let frames = self.shared.frames_after.load(Ordering::Acquire);
+        let mut anchor = self.shared.anchor.lock().unwrap();
+        anchor.time = anchor.frames_to_time(frames);
+        anchor.frames = frames;
+        anchor.sample_rate = sample_rate;
+    }
+
+    /// Called by the output callback with the number of source frames it
+    /// consumed and the delay until the first of them reaches the speaker.
+    pub fn advance(&self, frames: usize, latency: Duration) {
+        let shared = &self.shared;
+        let before = shared
+            .frames_after
+            .fetch_add(frames as u64, Ordering::AcqRel);
+        shared.frames_before.store(before, Ordering::Release);
+        shared
+            .latency
+            .store(latency.as_nanos() as u64, Ordering::Release);
+        // never 0, which means no callback yet
+        let now = shared.epoch.elapsed().as_nanos() as u64;
+        shared.last_callback.store(now.max(1), Ordering::Release);
+    }
+
+    /// Moves the clock to `time`, e.g. after a seek.
+    pub fn reset(&self, time: Duration) {
+        let frames = self.shared.frames_after.load(Ordering::Acquire);
+        let mut anchor = self.shared.anchor.lock().unwrap();
+        anchor.time = time;
+        anchor.frames = frames;
+    }
+
+    /// Source time of the frame that is audible right now.
+    pub fn now(&self) -> Duration {
+        let shared = &self.shared;
+        let anchor = shared.anchor.lock().unwrap();
+        let last_callback = shared.last_callback.load(Ordering::Acquire);
+        if last_callback == 0 {
+            return anchor.time;
+        }
+
+        // the first frame of the last callback plays `latency` after it ran,
+        // and playback never runs past the frames consumed so far
+        let start = anchor.frames_to_time(shared.frames_before.load(Ordering::Acquire));
+        let end = anchor.frames_to_time(shared.frames_after.load(Ordering::Acquire));
+        let latency = Duration::from_nanos(shared.latency.load(Ordering::Acquire));
+        let since_callback = shared
+            .epoch
+            .elapsed()
+            .saturating_sub(Duration::from_nanos(last_callback));
+
+        let audible = (start + since_callback).saturating_sub(latency);
+        audible.clamp(anchor.time, end)
+    }
+}
@@ -0,0 +1,133 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host};
+
+/// Output device picked with `--host` and `--device`, falling back to the
+/// default host and its default output device.
+#[derive(Debug, Clone, Default)]
+pub struct OutputSelection {
+    /// host name, e.g. ALSA or JACK
+    pub host: Option<String>,
+    /// device name or index in the host's output device list
+    pub device: Option<String>,
+}
+
+impl OutputSelection {
+    pub fn open(&self) -> Result<Device, Box<dyn std::error::Error>> {
+        let host = self.open_host()?;
+        let Some(wanted) = &self.device else {
+            return host.default_output_device().ok_or_else(|| {
+                format!("no default output device on host {}", host.id().name()).into()
+            });
+        };
+
+        let devices: Vec<Device> = host.output_devices()?.collect();
+        let names: Vec<String> = devices.iter().map(device_name).collect();
+
+        let found = match wanted.parse::<usize>() {
+            Ok(index) => (index < devices.len()).then_some(index),
+            Err(_) => names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(wanted))
+                .or_else(|| {
+                    // fall back to a unique partial match
+                    let wanted = wanted.to_lowercase();
+                    let mut matches = names
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, name)| name.to_lowercase().contains(&wanted));
+                    match (matches.next(), matches.next()) {
+                        (Some((index, _)), None) => Some(index),
+                        _ => None,
+                    }
+                }),
+        };
+
+        match found {
+            Some(index) => Ok(devices.into_iter().nth(index).unwrap()),
+            None => Err(format!(
+                "output device '{}' not found on host {}, available: {}",
+                wanted,
+                host.id().name(),
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            )
+            .into()),
+        }
+    }
+
+    fn open_host(&self) -> Result<Host, Box<dyn std::error::Error>> {
+        let Some(wanted) = &self.host else {
+            return Ok(cpal::default_host());
+        };
+
+        let hosts = cpal::available_hosts();
+        let id = hosts
+            .iter()
+            .find(|id| id.name().eq_ignore_ascii_case(wanted))
+            .ok_or_else(|| {
+                let names: Vec<&str> = hosts.iter().map(|id| id.name()).collect();
+                format!(
+                    "audio host '{}' not available, available: {}",
+                    wanted,
+                    names.join(", ")
+                )
+            })?;
+        Ok(cpal::host_from_id(*id)?)
+    }
+}
+
+/// Prints the output devices of every available host, or only of `host`.
+pub fn list_output_devices(host: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    // fail on unknown host names instead of printing nothing
+    if host.is_some() {
+        OutputSelection {
+            host: host.cloned(),
+            device: None,
+        }
+        .open_host()?;
+    }
+
+    let default_host = cpal::default_host().id();
+    for id in cpal::available_hosts() {
+        if host.is_some_and(|wanted| !id.name().eq_ignore_ascii_case(wanted)) {
+            continue;
+        }
+
+        let host = cpal::host_from_id(id)?;
+        println!(
+            "{}{}",
+            id.name(),
+            if id == default_host { " (default)" } else { "" }
+        );
+
+        let default_name = host
+            .default_output_device()
+            .map(|device| device_name(&device));
+        for (index, device) in host.output_devices()?.enumerate() {
+            let name = device_name(&device);
+            let config = device
+                .default_output_config()
+                .map(|config| format!("{} ch, {} Hz", config.channels(), config.sample_rate().0))
+                .unwrap_or_else(|_| "no default config".to_string());
+            let is_default = default_name.as_ref() == Some(&name);
+
+            println!(
+                "  {}: {} [{}]{}",
+                index,
+                name,
+                config,
+                if is_default { " (default)" } else { "" }
+            );
+        }
+    }
+    Ok(())
+}
+
+fn device_name(device: &Device) -> String {
+    device
+        .name()
+        .unwrap_or_else(|_| "unknown device".to_string())
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::aux::AnalysisResult;
+use crate::chroma::Chroma;
+use crate::key::Key;
+use crate::notes::{BandResult, Chord, NOTE_NAMES, Pitch, pitch_class_name};
+use crate::offline::OfflineAnalysis;
+
+/// File formats for an analysis timeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    /// Guesses the format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("unknown format '{}', expected json or csv", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Json => write!(f, "json"),
+            ExportFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// Writes results in timestamp order, with times in seconds. JSON also
+/// carries the key, tempo and beats of the whole signal.
+pub fn write_analysis(
+    analysis: &OfflineAnalysis,
+    format: ExportFormat,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    match format {
+        ExportFormat::Json => write_json(analysis, writer),
+        ExportFormat::Csv => write_csv(&analysis.results, writer),
+    }
+}
+
+fn write_json(analysis: &OfflineAnalysis, writer: &mut dyn Write) -> io::Result<()> {
+    let results = &analysis.results;
+    writeln!(writer, "{{")?;
+    writeln!(writer, "  \"key\": {},", json_key(analysis.key.as_ref()))?;
+    writeln!(
+        writer,
+        "  \"tempo\": {},",
+        analysis
+            .tempo
+            .map(|bpm| format!("{:.2}", bpm))
+            .unwrap_or_else(|| "null".to_string())
+    )?;
+    writeln!(writer, "  \"beats\": [{}],", json_times(&analysis.beats))?;
+    writeln!(writer, "  \"results\": [")?;
+    for (i, result) in results.iter().enumerate() {
+        let separator = if i + 1 < results.len() { "," } else { "" };
+        writeln!(
+            writer,
+            "    {{ \"time\": {:.6}, \"note\": {}, \"bands\": [{}], \"pitches\": [{}], \"chord\": {}, \"chroma\": [{}], \"key\": {}, \"beats\": [{}] }}{}",
+            result.timestamp.as_secs_f64(),
+            json_string(&result.to_string()),
+            result
+                .bands
+                .iter()
+                .map(json_band)
+                .collect::<Vec<String>>()
+                .join(", "),
+            result
+                .pitches
+                .iter()
+                .map(|pitch| json_pitch(&pitch.pitch, Some(pitch.salience)))
+                .collect::<Vec<String>>()
+                .join(", "),
+            json_chord(result.chord.as_ref()),
+            chroma_values(&result.chroma, ", "),
+            json_key(result.key.as_ref()),
+            json_times(&result.beats),
+            separator
+        )?;
+    }
+    writeln!(writer, "  ]")?;
+    writeln!(writer, "}}")
+}
+
+fn write_csv(results: &[AnalysisResult], writer: &mut dyn Write) -> io::Result<()> {
+    let chroma_header: Vec<String> = NOTE_NAMES
+        .iter()
+        .map(|name| format!("chroma_{}", name))
+        .collect();
+    writeln!(
+        writer,
+        "time,note,pitches,chord,key,tempo,beat,{}",
+        chroma_header.join(",")
+    )?;
+    for result in results {
+        writeln!(
+            writer,
+            "{:.6},{},{},{},{},{},{},{}",
+            result.timestamp.as_secs_f64(),
+            csv_field(&result.to_string()),
+            result
+                .pitches
+                .iter()
+                .map(|pitch| format!("{}{}", pitch.pitch.name(), pitch.pitch.octave))
+                .collect::<Vec<String>>()
+                .join(" "),
+            result
+                .chord
+                .map(|chord| chord.to_string())
+                .unwrap_or_default(),
+            result.key.map(|key| key.to_string()).unwrap_or_default(),
+            result
+                .tempo
+                .map(|bpm| format!("{:.2}", bpm))
+                .unwrap_or_default(),
+            if result.beats.is_empty() { 0 } else { 1 },
+            chroma_values(&result.chroma, ",")
+        )?;
+    }
+    Ok(())
+}
+
+fn chroma_values(chroma: &Chroma, separator: &str) -> String {
+    chroma
+        .iter()
+        .map(|value| format!("{:.3}", value))
+        .collect::<Vec<String>>()
+        .join(separator)
+}
+
+fn json_times(times: &[Duration]) -> String {
+    times
+        .iter()
+        .map(|time| format!("{:.6}", time.as_secs_f64()))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn json_key(key: Option<&Key>) -> String {
+    match key {
+        Some(key) => format!(
+            "{{ \"tonic\": {}, \"mode\": {}, \"confidence\": {:.3} }}",
+            json_string(pitch_class_name(key.tonic)),
+            json_string(&key.mode.to_string()),
+            key.confidence
+        ),
+        None => "null".to_string(),
+    }
+}
+
+fn json_band(band: &BandResult) -> String {
+    let pitches = band
+        .pitches
+        .iter()
+        .map(|pitch| json_pitch(pitch, None))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "{{ \"pitches\": [{}], \"chord\": {} }}",
+        pitches,
+        json_chord(band.chord.as_ref())
+    )
+}
+
+fn json_pitch(pitch: &Pitch, salience: Option<f32>) -> String {
+    let salience = salience
+        .map(|salience| format!(", \"salience\": {:.3}", salience))
+        .unwrap_or_default();
+    format!(
+        "{{ \"midi\": {}, \"name\": {}, \"octave\": {}, \"cents\": {:.1}, \"frequency\": {:.2}{} }}",
+        pitch.midi,
+        json_string(pitch.name()),
+        pitch.octave,
+        pitch.cents,
+        pitch.frequency,
+        salience
+    )
+}
+
+fn json_chord(chord: Option<&Chord>) -> String {
+    match chord {
+        Some(chord) => format!(
+            "{{ \"root\": {}, \"quality\": {}, \"bass\": {}, \"confidence\": {:.3} }}",
+            json_string(pitch_class_name(chord.root)),
+            json_string(chord.quality.name()),
+            chord
+                .bass
+                .map(|bass| json_string(pitch_class_name(bass)))
+                .unwrap_or_else(|| "null".to_string()),
+            chord.confidence
+        ),
+        None => "null".to_string(),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// quotes fields that would otherwise break the row
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
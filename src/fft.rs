@@ -1,97 +1,324 @@
-use realfft::{RealFftPlanner, num_complex::Complex};
+use std::f32::consts::PI;
+use std::sync::Arc;
 
-pub fn fft_chunks(
-    window_samples: &[Vec<f32>],
-    window_size: usize,
-    sample_rate: f32,
-    k_per_band: usize,
-) -> Result<Vec<Vec<Vec<f32>>>, Box<dyn std::error::Error>> {
-    let mut planner = RealFftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(window_size);
+use realfft::{RealFftPlanner, RealToComplex, num_complex::Complex};
 
-    let mut frequencies = Vec::with_capacity(window_samples.len());
-    let mut spectrum = vec![Complex::default(); window_size / 2 + 1];
+use crate::window::StftConfig;
 
-    let mut chunk = vec![0.0f32; window_size];
-    for window in window_samples {
-        chunk.copy_from_slice(window);
-        fft.process(&mut chunk, &mut spectrum)?;
+// frequency bands searched for the strongest peaks
+const BANDS: [(f32, f32); 4] = [
+    (50.0, 250.0),    // low
+    (250.0, 800.0),   // low-mid
+    (800.0, 2000.0),  // mid
+    (2000.0, 6000.0), // high
+];
 
-        let band_frequencies =
-            analyze_frequency_bands(&spectrum, sample_rate, window_size, k_per_band);
-        frequencies.push(band_frequencies);
-    }
-    Ok(frequencies)
+/// A peak of the spectrum, placed between bins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectralPeak {
+    pub frequency: f32,
+    pub magnitude: f32,
 }
 
-pub fn fft_chunk(
-    window_sample: &[f32],
-    sample_rate: f32,
-    k_per_band: usize,
-) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
-    let window_size = window_sample.len();
-    let mut planner = RealFftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(window_size);
+/// Forward transform of one stft config, planned once. The window and the
+/// input, scratch and output buffers are kept between windows, so analyzing
+/// one allocates nothing.
+pub struct SpectrumAnalyzer {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Arc<[f32]>,
+    input: Vec<f32>,
+    scratch: Vec<Complex<f32>>,
+    spectrum: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+    // peaks of the last frame, the strongest of each band and the scratch
+    // they are ranked in, refilled on every call
+    peaks: Vec<SpectralPeak>,
+    band_peaks: Vec<Vec<SpectralPeak>>,
+    candidates: Vec<(f32, SpectralPeak)>,
+    // hop between consecutive windows when peaks are refined by the phase
+    // change since the previous window
+    phase_hop: Option<usize>,
+    // phases of the last window and of the one before it, if there were any
+    phases: Vec<f32>,
+    previous_phases: Vec<f32>,
+    has_phases: bool,
+    has_previous: bool,
+}
+
+impl SpectrumAnalyzer {
+    /// Analyzer for the windows of `stft`, refining peaks by phase when it
+    /// asks for that.
+    pub fn from_stft(stft: &StftConfig) -> Self {
+        let fft_size = stft.fft_size;
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let analyzer = Self {
+            window: stft.window.coefficients(stft.window_size),
+            input: fft.make_input_vec(),
+            scratch: fft.make_scratch_vec(),
+            spectrum: fft.make_output_vec(),
+            magnitudes: vec![0.0; fft_size / 2 + 1],
+            peaks: Vec::new(),
+            band_peaks: vec![Vec::new(); BANDS.len()],
+            candidates: Vec::new(),
+            phase_hop: None,
+            phases: vec![0.0; fft_size / 2 + 1],
+            previous_phases: vec![0.0; fft_size / 2 + 1],
+            has_phases: false,
+            has_previous: false,
+            fft,
+        };
+        if stft.phase_refinement {
+            analyzer.with_phase_refinement(stft.hop_size)
+        } else {
+            analyzer
+        }
+    }
+
+    /// Refines peaks from the phase change between consecutive windows
+    /// `hop_size` samples apart, the instantaneous frequency of a phase
+    /// vocoder. Frames must then be processed in order, see `reset`.
+    pub fn with_phase_refinement(mut self, hop_size: usize) -> Self {
+        self.phase_hop = Some(hop_size);
+        self
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Forgets the previous window, for when the next one doesn't follow it.
+    pub fn reset(&mut self) {
+        self.has_phases = false;
+        self.has_previous = false;
+    }
+
+    /// Tapers one window of samples and transforms it, zero-padded up to
+    /// the fft size.
+    pub fn process(&mut self, samples: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+        if samples.len() != self.window.len() {
+            return Err(format!(
+                "got {} samples for a window of {}",
+                samples.len(),
+                self.window.len()
+            )
+            .into());
+        }
+        for ((input, sample), coeff) in self.input.iter_mut().zip(samples).zip(self.window.iter()) {
+            *input = sample * coeff;
+        }
+        self.input[samples.len()..].fill(0.0);
+        self.fft
+            .process_with_scratch(&mut self.input, &mut self.spectrum, &mut self.scratch)?;
+
+        for (magnitude, bin) in self.magnitudes.iter_mut().zip(&self.spectrum) {
+            *magnitude = bin.norm();
+        }
+        if self.phase_hop.is_some() {
+            std::mem::swap(&mut self.phases, &mut self.previous_phases);
+            self.has_previous = self.has_phases;
+            for (phase, bin) in self.phases.iter_mut().zip(&self.spectrum) {
+                *phase = bin.arg();
+            }
+            self.has_phases = true;
+        }
+        Ok(())
+    }
+
+    /// Magnitudes of the last processed frame, bin i is at
+    /// i * sample_rate / fft_size Hz.
+    pub fn magnitudes(&self) -> &[f32] {
+        &self.magnitudes
+    }
+
+    /// Every peak of the last processed frame, lowest first.
+    pub fn peaks(&mut self, sample_rate: f32) -> &[SpectralPeak] {
+        let bin_hz = sample_rate / self.fft_size() as f32;
+        self.peaks.clear();
+        let magnitudes = &self.magnitudes;
+        for bin in 1..magnitudes.len().saturating_sub(1) {
+            if magnitudes[bin] <= 0.0
+                || magnitudes[bin] <= magnitudes[bin - 1]
+                || magnitudes[bin] < magnitudes[bin + 1]
+            {
+                continue;
+            }
+            let (offset, magnitude) = interpolate_peak(magnitudes, bin);
+            let position = self.instantaneous_bin(bin).unwrap_or(bin as f32 + offset);
+            self.peaks.push(SpectralPeak {
+                frequency: position * bin_hz,
+                magnitude,
+            });
+        }
+        &self.peaks
+    }
+
+    /// Strongest peaks of each band of the last processed frame.
+    pub fn bands(&mut self, sample_rate: f32, k_per_band: usize) -> &[Vec<SpectralPeak>] {
+        if k_per_band == 0 {
+            self.band_peaks.iter_mut().for_each(Vec::clear);
+            return &self.band_peaks;
+        }
+        self.peaks(sample_rate);
+        analyze_frequency_bands(
+            &self.peaks,
+            k_per_band,
+            &mut self.candidates,
+            &mut self.band_peaks,
+        );
+        &self.band_peaks
+    }
 
-    let mut spectrum = vec![Complex::default(); window_size / 2 + 1];
-    let mut chunk = window_sample.to_vec();
-    fft.process(&mut chunk, &mut spectrum)?;
+    // where between bins the energy of `bin` really is, from how far its
+    // phase moved since the previous window. `None` without a previous
+    // window or when the answer lands outside the peak
+    fn instantaneous_bin(&self, bin: usize) -> Option<f32> {
+        let hop = self.phase_hop? as f32;
+        if !self.has_previous {
+            return None;
+        }
+        let fft_size = self.fft_size() as f32;
 
-    let band_frequencies = analyze_frequency_bands(&spectrum, sample_rate, window_size, k_per_band);
+        // phase a sine centered on the bin would advance by over one hop
+        let expected = 2.0 * PI * bin as f32 * hop / fft_size;
+        let deviation = wrap_phase(self.phases[bin] - self.previous_phases[bin] - expected);
+        let offset = deviation * fft_size / (2.0 * PI * hop);
+        (offset.abs() <= 1.0).then_some(bin as f32 + offset)
+    }
+}
 
-    Ok(band_frequencies)
+/// Offset in bins of the top of a parabola through the log magnitudes
+/// around the peak at `bin`, and its height. Exact for the gaussian shaped
+/// peaks of a gaussian window and close for the others.
+pub fn interpolate_peak(magnitudes: &[f32], bin: usize) -> (f32, f32) {
+    let log = [bin - 1, bin, bin + 1].map(|i| magnitudes[i].max(f32::MIN_POSITIVE).ln());
+    let offset = parabolic_offset(&log, 1);
+    let height = log[1] - 0.25 * (log[0] - log[2]) * offset;
+    (offset, height.exp())
 }
 
+/// Offset, -0.5 to 0.5, of the top or bottom of a parabola through
+/// `values` around `index`, 0 at either end.
+pub fn parabolic_offset(values: &[f32], index: usize) -> f32 {
+    if index == 0 || index + 1 >= values.len() {
+        return 0.0;
+    }
+    let (left, center, right) = (values[index - 1], values[index], values[index + 1]);
+    let denominator = left - 2.0 * center + right;
+    if denominator.abs() <= f32::EPSILON {
+        return 0.0;
+    }
+    (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+}
+
+// into -π to π
+fn wrap_phase(phase: f32) -> f32 {
+    phase - 2.0 * PI * (phase / (2.0 * PI)).round()
+}
+
+// the `k_per_band` strongest of `peaks` in each band into `band_peaks`,
+// ranked in `candidates`
 fn analyze_frequency_bands(
-    spectrum: &[Complex<f32>],
-    sample_rate: f32,
-    window_size: usize,
+    peaks: &[SpectralPeak],
     k_per_band: usize,
-) -> Vec<Vec<f32>> {
-    let bands = [
-        (50.0, 250.0),    // low
-        (250.0, 800.0),   // low-mid
-        (800.0, 2000.0),  // mid
-        (2000.0, 6000.0), // high
-    ];
-    let mut band_peaks: Vec<Vec<f32>> = vec![Vec::new(); bands.len()];
-    for (i, (low_freq, high_freq)) in bands.iter().enumerate() {
-        let low_bin = ((low_freq * window_size as f32) / sample_rate) as usize;
-        let high_bin = ((high_freq * window_size as f32) / sample_rate) as usize;
-        let mut candidate_freqs: Vec<(f32, f32)> = Vec::new();
-
-        // iter frequencies in band range
-        // spectrum is a vec (size: window_size.len() / 2 + 1)
-        // which index called bin rep a freq
-        for (bin, freq_vec) in spectrum
+    candidates: &mut Vec<(f32, SpectralPeak)>,
+    band_peaks: &mut [Vec<SpectralPeak>],
+) {
+    for ((low_freq, high_freq), band) in BANDS.iter().zip(band_peaks.iter_mut()) {
+        candidates.clear();
+        candidates.extend(
+            peaks
+                .iter()
+                .filter(|peak| peak.frequency >= *low_freq && peak.frequency < *high_freq)
+                .map(|peak| {
+                    let weight = if peak.frequency > 400.0 {
+                        (peak.frequency / 400.0).sqrt().min(2.0)
+                    } else {
+                        1.0
+                    };
+                    (peak.magnitude * weight, *peak)
+                }),
+        );
+
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        band.clear();
+        band.extend(candidates.iter().take(k_per_band).map(|(_, peak)| *peak));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::WindowKind;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    fn sine(frequency: f32, start: usize, len: usize) -> Vec<f32> {
+        (start..start + len)
+            .map(|i| (2.0 * PI * frequency * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    // position in bins of the strongest peak of the last frame
+    fn strongest_bin(analyzer: &mut SpectrumAnalyzer) -> f32 {
+        let bin_hz = SAMPLE_RATE / analyzer.fft_size() as f32;
+        let peak = analyzer
+            .peaks(SAMPLE_RATE)
             .iter()
-            .enumerate()
-            .take(high_bin.min(spectrum.len()))
-            .skip(low_bin)
-        {
-            let magnitude = freq_vec.norm();
-
-            let frequency = (bin as f32 * sample_rate) / window_size as f32;
-            let weight = if frequency > 400.0 {
-                (frequency / 400.0).sqrt().min(2.0)
-            } else {
-                1.0
-            };
-
-            let weighted_magnitude = magnitude * weight;
-
-            candidate_freqs.push((weighted_magnitude, frequency));
+            .max_by(|a, b| a.magnitude.total_cmp(&b.magnitude))
+            .unwrap();
+        peak.frequency / bin_hz
+    }
+
+    #[test]
+    fn parabolic_offset_finds_the_vertex() {
+        // y = -(x - 1.25)^2 sampled at 0, 1 and 2
+        let values = [-1.5625, -0.0625, -0.5625];
+        assert!((parabolic_offset(&values, 1) - 0.25).abs() < 1e-6);
+        assert_eq!(parabolic_offset(&values, 0), 0.0);
+        assert_eq!(parabolic_offset(&[1.0, 1.0, 1.0], 1), 0.0);
+    }
+
+    #[test]
+    fn places_a_sine_between_bins() {
+        let stft = StftConfig::new(2048, WindowKind::Hann);
+        let bin_hz = SAMPLE_RATE / stft.fft_size as f32;
+        for position in [100.0, 100.25, 100.5, 100.8] {
+            let mut analyzer = SpectrumAnalyzer::from_stft(&stft);
+            analyzer
+                .process(&sine(position * bin_hz, 0, stft.window_size))
+                .unwrap();
+            let found = strongest_bin(&mut analyzer);
+            assert!((found - position).abs() < 0.05, "{} at {}", position, found);
         }
+    }
 
-        candidate_freqs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-        let top_k_freqs: Vec<f32> = candidate_freqs
-            .into_iter()
-            .take(k_per_band)
-            .map(|(_, freq)| freq)
-            .collect();
+    #[test]
+    fn phase_refinement_beats_bin_centers() {
+        let stft = StftConfig::new(2048, WindowKind::Hann).with_phase_refinement(true);
+        let bin_hz = SAMPLE_RATE / stft.fft_size as f32;
+        for position in [100.3, 100.45, 250.7] {
+            let frequency = position * bin_hz;
+            let mut analyzer = SpectrumAnalyzer::from_stft(&stft);
+            for start in [0, stft.hop_size] {
+                analyzer
+                    .process(&sine(frequency, start, stft.window_size))
+                    .unwrap();
+            }
+            // the estimate comes from the phase, not the parabola
+            let bin = position.round() as usize;
+            assert!(analyzer.instantaneous_bin(bin).is_some());
 
-        band_peaks[i] = top_k_freqs;
+            let found = strongest_bin(&mut analyzer);
+            let error = (found - position).abs();
+            assert!(error < 0.05, "{} at {}", position, found);
+            assert!(error < (position.round() - position).abs());
+        }
     }
 
-    band_peaks
+    #[test]
+    fn rejects_a_window_of_the_wrong_size() {
+        let mut analyzer = SpectrumAnalyzer::from_stft(&StftConfig::new(1024, WindowKind::Hann));
+        assert!(analyzer.process(&[0.0; 512]).is_err());
+    }
 }
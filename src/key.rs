@@ -0,0 +1,239 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::chroma::Chroma;
+use crate::notes::pitch_class_name;
+
+// Krumhansl-Kessler key profiles, tonic first
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+// relative keys closer than this are told apart by their tonic triads
+const RELATIVE_MARGIN: f32 = 0.1;
+// how long the rolling estimate remembers, and the gap that counts as a seek
+const ROLLING_TIME_CONSTANT: Duration = Duration::from_secs(8);
+const MAX_GAP: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+impl Mode {
+    fn profile(&self) -> &'static [f32; 12] {
+        match self {
+            Mode::Major => &MAJOR_PROFILE,
+            Mode::Minor => &MINOR_PROFILE,
+        }
+    }
+
+    fn third(&self) -> u8 {
+        match self {
+            Mode::Major => 4,
+            Mode::Minor => 3,
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Major => write!(f, "major"),
+            Mode::Minor => write!(f, "minor"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Key {
+    /// pitch class of the tonic
+    pub tonic: u8,
+    pub mode: Mode,
+    /// 0 to 1, correlation of the chroma with the key's profile
+    pub confidence: f32,
+}
+
+impl Key {
+    /// The major or minor key with the same notes.
+    pub fn relative(&self) -> (u8, Mode) {
+        match self.mode {
+            Mode::Major => ((self.tonic + 9) % 12, Mode::Minor),
+            Mode::Minor => ((self.tonic + 3) % 12, Mode::Major),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", pitch_class_name(self.tonic), self.mode)
+    }
+}
+
+/// Correlates `chroma` with the profile of all 24 major and minor keys and
+/// returns the best fit. `None` for silence.
+pub fn estimate_key(chroma: &Chroma) -> Option<Key> {
+    if chroma.iter().all(|&value| value <= 0.0) {
+        return None;
+    }
+
+    let mut scores = Vec::with_capacity(24);
+    for mode in [Mode::Major, Mode::Minor] {
+        for tonic in 0..12u8 {
+            let rotated: [f32; 12] =
+                std::array::from_fn(|i| mode.profile()[(i + 12 - tonic as usize) % 12]);
+            scores.push((tonic, mode, correlation(chroma, &rotated)));
+        }
+    }
+
+    // ties go to the first key, so the result is deterministic
+    let (tonic, mode, score) = scores
+        .iter()
+        .copied()
+        .reduce(|best, next| if next.2 > best.2 { next } else { best })?;
+    let mut key = Key {
+        tonic,
+        mode,
+        confidence: score.clamp(0.0, 1.0),
+    };
+
+    // relative keys share their notes, so the profiles barely separate them
+    let (relative_tonic, relative_mode) = key.relative();
+    let relative_score = scores
+        .iter()
+        .find(|(tonic, mode, _)| *tonic == relative_tonic && *mode == relative_mode)
+        .map(|(_, _, score)| *score)?;
+    if score - relative_score < RELATIVE_MARGIN
+        && triad_energy(chroma, relative_tonic, relative_mode) > triad_energy(chroma, tonic, mode)
+    {
+        key = Key {
+            tonic: relative_tonic,
+            mode: relative_mode,
+            confidence: relative_score.clamp(0.0, 1.0),
+        };
+    }
+    Some(key)
+}
+
+/// Rolling key estimate over recent chroma, older frames fading out.
+#[derive(Default)]
+pub struct KeyTracker {
+    chroma: Chroma,
+    last_time: Option<Duration>,
+}
+
+impl KeyTracker {
+    /// Adds the chroma of the frame at `time` and returns the current estimate.
+    pub fn update(&mut self, chroma: &Chroma, time: Duration) -> Option<Key> {
+        let decay = match self.last_time {
+            // jumps, e.g. seeks, start over
+            Some(last) if time >= last && time - last <= MAX_GAP => {
+                (-(time - last).as_secs_f32() / ROLLING_TIME_CONSTANT.as_secs_f32()).exp()
+            }
+            _ => 0.0,
+        };
+        self.last_time = Some(time);
+
+        for (total, value) in self.chroma.iter_mut().zip(chroma) {
+            *total = *total * decay + value;
+        }
+        estimate_key(&self.chroma)
+    }
+}
+
+// energy of the tonic, third and fifth
+fn triad_energy(chroma: &Chroma, tonic: u8, mode: Mode) -> f32 {
+    [0, mode.third(), 7]
+        .iter()
+        .map(|interval| chroma[((tonic + interval) % 12) as usize])
+        .sum()
+}
+
+// pearson correlation
+fn correlation(a: &[f32; 12], b: &[f32; 12]) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / 12.0;
+    let mean_b = b.iter().sum::<f32>() / 12.0;
+
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        let (dx, dy) = (x - mean_a, y - mean_b);
+        covariance += dx * dy;
+        variance_a += dx * dx;
+        variance_b += dy * dy;
+    }
+
+    let denominator = (variance_a * variance_b).sqrt();
+    if denominator <= f32::EPSILON {
+        0.0
+    } else {
+        covariance / denominator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const C_MAJOR_SCALE: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+    // the scale at 1 with its tonic triad at 2, as in music that dwells on it
+    fn scale_chroma(scale: &[u8], triad: [u8; 3]) -> Chroma {
+        let mut chroma = [0.0; 12];
+        for &pitch_class in scale {
+            chroma[pitch_class as usize] = 1.0;
+        }
+        for pitch_class in triad {
+            chroma[pitch_class as usize] = 2.0;
+        }
+        chroma
+    }
+
+    fn tonic_and_mode(key: Option<Key>) -> Option<(u8, Mode)> {
+        key.map(|key| (key.tonic, key.mode))
+    }
+
+    #[test]
+    fn finds_c_major() {
+        let chroma = scale_chroma(&C_MAJOR_SCALE, [0, 4, 7]);
+        assert_eq!(
+            tonic_and_mode(estimate_key(&chroma)),
+            Some((0, Mode::Major))
+        );
+    }
+
+    #[test]
+    fn tells_a_minor_from_its_relative_major() {
+        // the same notes as c major, dwelling on a, c and e
+        let chroma = scale_chroma(&C_MAJOR_SCALE, [9, 0, 4]);
+        assert_eq!(
+            tonic_and_mode(estimate_key(&chroma)),
+            Some((9, Mode::Minor))
+        );
+    }
+
+    #[test]
+    fn silence_has_no_key() {
+        assert_eq!(estimate_key(&[0.0; 12]), None);
+    }
+
+    #[test]
+    fn tracker_starts_over_after_a_jump() {
+        let mut tracker = KeyTracker::default();
+        let c_major = scale_chroma(&C_MAJOR_SCALE, [0, 4, 7]);
+        let a_minor = scale_chroma(&C_MAJOR_SCALE, [9, 0, 4]);
+
+        for frame in 0..20 {
+            tracker.update(&c_major, Duration::from_millis(100 * frame));
+        }
+        // right after c major, one a minor frame doesn't outweigh it
+        let key = tracker.update(&a_minor, Duration::from_millis(2000));
+        assert_eq!(tonic_and_mode(key), Some((0, Mode::Major)));
+
+        // a seek forgets what came before
+        let key = tracker.update(&a_minor, Duration::from_secs(60));
+        assert_eq!(tonic_and_mode(key), Some((9, Mode::Minor)));
+    }
+}
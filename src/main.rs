@@ -1,32 +1,59 @@
 mod analyzer;
 mod audio;
 mod aux;
+mod backend;
+mod beat;
+mod capture;
+mod channels;
+mod chroma;
+mod clock;
+mod device;
+mod export;
 mod fft;
+mod key;
+mod midi;
+mod multipitch;
 mod notes;
+mod offline;
+mod plot;
+mod resample;
+mod ring;
 mod soundboard;
+mod source;
 mod stream;
 mod synth;
 mod visualizer;
 mod window;
+mod yin;
 
-use std::io::BufRead;
-use std::ops::Deref;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
 use std::sync::{Arc, Mutex, mpsc};
 use std::time::Duration;
-use std::{io, path, thread};
+use std::{io, thread};
 
-use crate::analyzer::AudioAnalyzer;
-use crate::audio::decode_audio_wav;
+use crate::analyzer::{AudioAnalyzer, PitchStrategy};
+use crate::audio::{StreamingDecoder, decode_audio, list_tracks};
 use crate::aux::AudioOutput;
+use crate::backend::{DeviceBackend, FileBackend, NullBackend, OutputBackend};
+use crate::capture::{start_device_capture, start_fake_capture, start_live_monitor};
+use crate::channels::ChannelMode;
+use crate::device::{OutputSelection, list_output_devices};
+use crate::export::{ExportFormat, write_analysis};
+use crate::midi::MidiOptions;
+use crate::source::{AudioSource, MemorySource, Signal, SignalSource};
 use crate::stream::AudioStreamer;
 use crate::visualizer::TerminalVisualizer;
+use crate::window::{StftConfig, WindowKind};
 
 static SAMPLE_RATE: f32 = 44100.0;
 
 enum OP {
     Synth,
     Analyze,
+    Capture,
+    ListDevices,
 }
 
 fn main() {
@@ -34,24 +61,119 @@ fn main() {
     let first_arg = args.get(1).unwrap().as_str();
     let op = match first_arg {
         "-s" => OP::Synth,
-        "-a" => OP::Analyze,
-        _ => panic!("must specify argument -r (record) or -a (analyze)"),
+        "analyze" | "-a" => OP::Analyze,
+        "-c" => OP::Capture,
+        "--list-devices" => OP::ListDevices,
+        _ => {
+            panic!(
+                "must specify argument -s (synth), analyze or -a, -c (capture) or --list-devices"
+            )
+        }
+    };
+
+    // where -s and analyze play their audio
+    let output = OutputSelection {
+        host: flag_value(&args, "--host").cloned(),
+        device: flag_value(&args, "--device").cloned(),
     };
+    let backend = output_backend(&args, output.clone()).unwrap_or_else(|err| panic!("{}", err));
+
+    let channel_mode = flag_value(&args, "--channel")
+        .map(|value| value.parse::<ChannelMode>())
+        .transpose()
+        .unwrap_or_else(|err| panic!("{}", err))
+        .unwrap_or_default();
+
+    // `--pitch bands|yin`, switched with p while running
+    let pitch_strategy = flag_value(&args, "--pitch")
+        .map(|value| value.parse::<PitchStrategy>())
+        .transpose()
+        .unwrap_or_else(|err| panic!("{}", err))
+        .unwrap_or_default();
+
+    let stft = stft_config(&args).unwrap_or_else(|err| panic!("{}", err));
 
     let should_main_quit = Arc::new(Mutex::new(false));
     let should_main_quit_clone = should_main_quit.clone();
-
-    match op {
+    // joined before exiting so outputs can finish, e.g. a wav file
+    let worker = match op {
         OP::Synth => {
-            let _ = synth::run_synthesizer(should_main_quit_clone);
+            // `--demo` plays a short melody instead of opening the keyboard
+            let demo = args.iter().any(|arg| arg == "--demo");
+            if let Err(err) = synth::run_synthesizer(should_main_quit_clone, backend.as_ref(), demo)
+            {
+                eprintln!("{}", err);
+            }
+            return;
+        }
+        OP::ListDevices => {
+            if let Err(err) = list_output_devices(output.host.as_ref()) {
+                eprintln!("{}", err);
+            }
+            return;
         }
         OP::Analyze => {
-            let path = args.get(2).expect("file path not provided").clone();
+            // a file path, unless a test signal is given instead
+            let path = args.get(2).filter(|arg| !arg.starts_with("--")).cloned();
+
+            if args.iter().any(|arg| arg == "--list-tracks") {
+                let path = path.expect("file path not provided");
+                match list_tracks(&path) {
+                    Ok(tracks) => tracks.iter().for_each(|track| println!("{}", track)),
+                    Err(err) => eprintln!("{}", err),
+                }
+                return;
+            }
+
+            if args.iter().any(|arg| arg == "--offline") {
+                let result = open_source(path.as_ref(), &args).and_then(|source| {
+                    let source = source.ok_or("file path or --signal not provided")?;
+                    analyze_offline(source, channel_mode, pitch_strategy, stft, &args)
+                });
+                if let Err(err) = result {
+                    eprintln!("{}", err);
+                }
+                return;
+            }
+
             thread::spawn(move || {
-                let _ = analyze_loop(&path, should_main_quit_clone);
-            });
+                let result = open_source(path.as_ref(), &args).and_then(|source| {
+                    let source = source.ok_or("file path or --signal not provided")?;
+                    analyze_loop(
+                        source,
+                        channel_mode,
+                        pitch_strategy,
+                        stft,
+                        backend.as_ref(),
+                        should_main_quit_clone.clone(),
+                    )
+                });
+                if let Err(err) = result {
+                    eprintln!("{}", err);
+                    *should_main_quit_clone.lock().unwrap() = true;
+                }
+            })
         }
-    }
+        OP::Capture => {
+            // feed a file or test signal through a fake input device instead of capturing
+            let path = flag_value(&args, "--input-file").cloned();
+            thread::spawn(move || {
+                let result = open_source(path.as_ref(), &args).and_then(|source| {
+                    capture_loop(
+                        source,
+                        channel_mode,
+                        pitch_strategy,
+                        stft,
+                        should_main_quit_clone.clone(),
+                    )
+                });
+                if let Err(err) = result {
+                    eprintln!("{}", err);
+                    *should_main_quit_clone.lock().unwrap() = true;
+                }
+            })
+        }
+    };
 
     // keep main loop alive and control threads
     loop {
@@ -67,43 +189,232 @@ fn main() {
 
         // println!("Current time: {:?}", streamer.get_current_time());
     }
+
+    let _ = worker.join();
+}
+
+// `--window-size <samples>`, `--hop <samples>` and `--fft-size <samples>`
+// for zero-padding, `--phase-vocoder` to refine peaks by phase, plus the
+// taper as
+// `--window hann|hamming|blackman|blackman-harris|flattop|kaiser[:beta]|gaussian[:sigma]`
+fn stft_config(args: &[String]) -> Result<StftConfig, String> {
+    let size = |flag: &str| {
+        flag_value(args, flag)
+            .map(|value| {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid {} '{}'", flag, value))
+            })
+            .transpose()
+    };
+    let window = flag_value(args, "--window")
+        .map(|value| value.parse::<WindowKind>())
+        .transpose()?
+        .unwrap_or_default();
+    let window_size = size("--window-size")?.unwrap_or(StftConfig::default().window_size);
+
+    let mut stft = StftConfig::new(window_size, window);
+    if let Some(hop_size) = size("--hop")? {
+        stft = stft.with_hop_size(hop_size);
+    }
+    if let Some(fft_size) = size("--fft-size")? {
+        stft = stft.with_fft_size(fft_size);
+    }
+    let stft = stft.with_phase_refinement(args.iter().any(|arg| arg == "--phase-vocoder"));
+    stft.validate()?;
+    Ok(stft)
+}
+
+// returns the value following `flag`, e.g. `--channel left`
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|idx| args.get(idx + 1))
+}
+
+// `--output null` discards audio and `--output <file>.wav` (or
+// `file:<path>`) records it, both at real-time pace unless `--fast` is given
+fn output_backend(
+    args: &[String],
+    output: OutputSelection,
+) -> Result<Box<dyn OutputBackend>, String> {
+    let realtime = !args.iter().any(|arg| arg == "--fast");
+    match flag_value(args, "--output").map(String::as_str) {
+        None | Some("device") => Ok(Box::new(DeviceBackend::new(output))),
+        Some("null") => Ok(Box::new(NullBackend::new(realtime))),
+        Some(value) => {
+            let path = match value.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => path,
+                Some(_) => return Err("--output file: needs a path".into()),
+                None if value.to_ascii_lowercase().ends_with(".wav") => value,
+                None => {
+                    return Err(format!(
+                        "unknown output '{}', expected device, null, <file>.wav or file:<path>",
+                        value
+                    ));
+                }
+            };
+            Ok(Box::new(FileBackend::new(path.into(), realtime)))
+        }
+    }
+}
+
+// opens the file at `path` or the test signal given by `--signal`
+// `--track <index>` picks a track and `--preload` decodes the file into memory
+fn open_source(
+    path: Option<&String>,
+    args: &[String],
+) -> Result<Option<Box<dyn AudioSource>>, Box<dyn std::error::Error>> {
+    if let Some(spec) = flag_value(args, "--signal") {
+        let signal = spec.parse::<Signal>()?;
+        let source = SignalSource::new(signal, SAMPLE_RATE, Duration::from_secs(30));
+        return Ok(Some(Box::new(source)));
+    }
+
+    let Some(path) = path else {
+        return Ok(None);
+    };
+
+    let track_index = flag_value(args, "--track")
+        .map(|value| value.parse::<usize>())
+        .transpose()
+        .map_err(|_| "invalid track index")?;
+
+    // the detected container and codec go to stderr, stdout may hold results
+    if args.iter().any(|arg| arg == "--preload") {
+        let decoded = decode_audio(path, SAMPLE_RATE, track_index)?;
+        eprintln!("{}", decoded.info);
+        Ok(Some(Box::new(MemorySource::from(decoded))))
+    } else {
+        let decoder = StreamingDecoder::open_track(path, SAMPLE_RATE, track_index)?;
+        eprintln!("{}", decoder.track_info());
+        Ok(Some(Box::new(decoder)))
+    }
+}
+
+// analyzes the whole source as fast as possible and writes the results
+// to `--out <file>` or stdout, as `--format json|csv`
+// `--midi <file>` also transcribes the notes, see `midi_options`
+fn analyze_offline(
+    source: Box<dyn AudioSource>,
+    channel_mode: ChannelMode,
+    pitch_strategy: PitchStrategy,
+    stft: StftConfig,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let out_path = flag_value(args, "--out");
+    let format = match flag_value(args, "--format") {
+        Some(format) => format.parse::<ExportFormat>()?,
+        None => out_path
+            .and_then(|path| ExportFormat::from_path(Path::new(path)))
+            .unwrap_or_default(),
+    };
+
+    let midi_path = flag_value(args, "--midi");
+    let midi_options = midi_options(args)?;
+
+    let analysis = offline::analyze_source(source, channel_mode, pitch_strategy, stft)?;
+
+    // results go to stdout unless only a midi file was asked for
+    if out_path.is_some() || midi_path.is_none() {
+        let mut writer: Box<dyn Write> = match out_path {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(BufWriter::new(io::stdout().lock())),
+        };
+        write_analysis(&analysis, format, &mut writer)?;
+        writer.flush()?;
+    }
+
+    if let Some(midi_path) = midi_path {
+        let frames = midi::detected_frames(&analysis);
+        let events = midi::transcribe(&frames, analysis.duration());
+        let mut writer = BufWriter::new(File::create(midi_path)?);
+        midi::write_midi(&events, midi_options, &mut writer)?;
+        writer.flush()?;
+    }
+
+    if let Some(plot_path) = flag_value(args, "--plot") {
+        plot::plot_waveform(&analysis.samples, analysis.sample_rate, plot_path)?;
+    }
+    Ok(())
+}
+
+// `--tempo <bpm>` and `--quantize <n>` to snap notes to 1/n notes
+fn midi_options(args: &[String]) -> Result<MidiOptions, Box<dyn std::error::Error>> {
+    let mut options = MidiOptions::default();
+    if let Some(tempo) = flag_value(args, "--tempo") {
+        options.tempo_bpm = tempo
+            .parse::<f64>()
+            .ok()
+            .filter(|bpm| *bpm > 0.0)
+            .ok_or("invalid tempo")?;
+    }
+    if let Some(division) = flag_value(args, "--quantize") {
+        options.quantize = Some(
+            division
+                .parse::<u32>()
+                .ok()
+                .filter(|division| *division > 0)
+                .ok_or("invalid quantization, expected e.g. 16 for sixteenth notes")?,
+        );
+    }
+    Ok(options)
 }
 
 fn analyze_loop(
-    path: &String,
+    source: Box<dyn AudioSource>,
+    channel_mode: ChannelMode,
+    pitch_strategy: PitchStrategy,
+    stft: StftConfig,
+    backend: &dyn OutputBackend,
     should_quit: Arc<Mutex<bool>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // load audio file
-    let (samples, sample_rate) = decode_audio_wav(path, SAMPLE_RATE)?;
-    let total_duration = Duration::from_secs_f32(samples.len() as f32 / sample_rate);
+    let sample_rate = source.sample_rate();
+    let total_duration = source.duration().unwrap_or(Duration::ZERO);
 
     // create visualizer
     let (visualizer, vis_data) = TerminalVisualizer::new(50, 80, 10);
+    vis_data.lock().unwrap().track_title = source.name();
     // create streamer
-    let (streamer, audio_rx, analysis_rx) = AudioStreamer::new(samples, sample_rate, 500);
+    let (streamer, audio_rx, analysis_rx) = AudioStreamer::new(source, 500);
+    let streamer = Arc::new(streamer);
+    let channels = streamer.channels();
+    channel_mode.validate(channels)?;
+
     let (analysis_result_tx, analysis_result_rx) = mpsc::channel();
     // start streaming data from mem
     streamer.start_streaming();
 
     // set up and start analyzer
-    let analyzer = AudioAnalyzer::new(sample_rate, analysis_result_tx);
+    let analyzer = AudioAnalyzer::new(
+        sample_rate,
+        channels,
+        channel_mode,
+        pitch_strategy,
+        stft,
+        analysis_result_tx,
+    );
     analyzer.run(analysis_rx);
+    let strategy = analyzer.strategy();
 
     // set up and start aux
     let mut audio_output = AudioOutput::new(
         audio_rx,
         analysis_result_rx,
         vis_data,
+        streamer.clock(),
         sample_rate,
+        channels,
         total_duration,
     );
-    let _stream = audio_output.start_playback(sample_rate)?;
+    let _stream = audio_output.start_playback(backend)?;
 
     // start visualizer
     visualizer.start_rendering();
 
     // input detection
     let should_quit_clone = should_quit.clone();
+    let input_streamer = streamer.clone();
 
     thread::spawn(move || {
         let stdin = io::stdin();
@@ -116,26 +427,30 @@ fn analyze_loop(
             }
             if line.trim().eq_ignore_ascii_case("k") {
                 audio_output.toggle();
-                streamer.toggle();
+                input_streamer.toggle();
+            }
+            if line.trim().eq_ignore_ascii_case("p") {
+                let mut strategy = strategy.lock().unwrap();
+                *strategy = strategy.toggled();
             }
 
             if line.trim().eq_ignore_ascii_case("l") {
                 // go foward 5 secs
-                let new_playback_time = streamer.skip_forward(5.0);
-                audio_output.clear_buffers();
-                audio_output.update_current_playback_time(new_playback_time);
+                audio_output.seek(|| input_streamer.skip_forward(5.0));
             }
             if line.trim().eq_ignore_ascii_case("j") {
                 // go backward 5 secs
-                let new_playback_time = streamer.skip_backward(5.0);
-                audio_output.clear_buffers();
-                audio_output.update_current_playback_time(new_playback_time);
+                audio_output.seek(|| input_streamer.skip_backward(5.0));
             }
         }
     });
 
     loop {
-        let should_quit = should_quit.lock().unwrap();
+        let mut should_quit = should_quit.lock().unwrap();
+        // nobody hears a headless output, so stop once it has played out
+        if backend.is_headless() && streamer.is_finished() {
+            *should_quit = true;
+        }
         if *should_quit {
             break;
         }
@@ -147,6 +462,79 @@ fn analyze_loop(
 
         // println!("Current time: {:?}", streamer.get_current_time());
     }
-    // visualizer.cleanup();
+    Ok(())
+}
+
+fn capture_loop(
+    fake_input: Option<Box<dyn AudioSource>>,
+    channel_mode: ChannelMode,
+    pitch_strategy: PitchStrategy,
+    stft: StftConfig,
+    should_quit: Arc<Mutex<bool>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // start capturing 250 ms blocks
+    let (_capture, capture) = match fake_input {
+        Some(source) => start_fake_capture(source, 250),
+        None => start_device_capture(250)?,
+    };
+    let sample_rate = capture.sample_rate();
+    let channels = capture.channels();
+    channel_mode.validate(channels)?;
+
+    // create visualizer
+    let (visualizer, vis_data) = TerminalVisualizer::new(50, 80, 10);
+    vis_data.lock().unwrap().track_title = capture.name();
+
+    // show captured audio and pass it on to the analyzer
+    let (analysis_result_tx, analysis_result_rx) = mpsc::channel();
+    let analysis_rx = start_live_monitor(Box::new(capture), 250, analysis_result_rx, vis_data);
+
+    // set up and start analyzer
+    let analyzer = AudioAnalyzer::new(
+        sample_rate,
+        channels,
+        channel_mode,
+        pitch_strategy,
+        stft,
+        analysis_result_tx,
+    );
+    analyzer.run(analysis_rx);
+    let strategy = analyzer.strategy();
+
+    // start visualizer
+    visualizer
+        .with_controls("Controls: [q]uit | [p] pitch bands/yin")
+        .start_rendering();
+
+    // input detection
+    let should_quit_clone = should_quit.clone();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        while let Some(Ok(line)) = lines.next() {
+            if line.trim().eq_ignore_ascii_case("q") {
+                let mut should_quit = should_quit_clone.lock().unwrap();
+                *should_quit = true;
+                break;
+            }
+            if line.trim().eq_ignore_ascii_case("p") {
+                let mut strategy = strategy.lock().unwrap();
+                *strategy = strategy.toggled();
+            }
+        }
+    });
+
+    loop {
+        let should_quit = should_quit.lock().unwrap();
+        if *should_quit {
+            break;
+        }
+
+        // explicitly drop lock bc of sleep
+        // avoid deadlock
+        drop(should_quit);
+        std::thread::sleep(Duration::from_millis(500));
+    }
     Ok(())
 }
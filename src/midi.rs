@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::offline::OfflineAnalysis;
+
+// ticks per quarter note
+const TICKS_PER_QUARTER: u16 = 480;
+// windows quieter than this are treated as silence
+const SILENCE_DB: f32 = -50.0;
+
+/// Notes detected in one analysis window.
+#[derive(Debug, Clone)]
+pub struct DetectedFrame {
+    pub time: Duration,
+    pub notes: Vec<u8>,
+    pub velocity: u8,
+}
+
+/// A note with its length, ready to be written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteEvent {
+    pub start: Duration,
+    pub end: Duration,
+    pub note: u8,
+    pub velocity: u8,
+}
+
+/// Tempo and grid of the written file.
+#[derive(Debug, Clone, Copy)]
+pub struct MidiOptions {
+    pub tempo_bpm: f64,
+    /// snap notes to 1/n notes, e.g. 16 for sixteenths
+    pub quantize: Option<u32>,
+    /// shorter detections are dropped as flicker
+    pub min_duration: Duration,
+}
+
+impl Default for MidiOptions {
+    fn default() -> Self {
+        Self {
+            tempo_bpm: 120.0,
+            quantize: None,
+            min_duration: Duration::from_millis(50),
+        }
+    }
+}
+
+/// The notes sounding in each window, skipping quiet windows.
+pub fn detected_frames(analysis: &OfflineAnalysis) -> Vec<DetectedFrame> {
+    analysis
+        .results
+        .iter()
+        .zip(&analysis.levels)
+        .map(|(result, &level)| {
+            let db = 20.0 * level.max(1e-9).log10();
+            let mut notes: Vec<u8> = if db < SILENCE_DB {
+                Vec::new()
+            } else {
+                result
+                    .pitches
+                    .iter()
+                    .map(|pitch| pitch.pitch.midi)
+                    .collect()
+            };
+            notes.sort_unstable();
+            notes.dedup();
+
+            // -60 dBFS and below is the quietest velocity
+            let velocity = ((db + 60.0) / 60.0 * 126.0 + 1.0).clamp(1.0, 127.0) as u8;
+            DetectedFrame {
+                time: result.timestamp,
+                notes,
+                velocity,
+            }
+        })
+        .collect()
+}
+
+/// Turns per-window detections into notes, merging a pitch that is
+/// detected in consecutive windows into one note. Notes still sounding
+/// at the last window end at `end`.
+pub fn transcribe(frames: &[DetectedFrame], end: Duration) -> Vec<NoteEvent> {
+    let mut active: HashMap<u8, (Duration, u8)> = HashMap::new();
+    let mut events = Vec::new();
+
+    for frame in frames {
+        active.retain(|&note, &mut (start, velocity)| {
+            let sounding = frame.notes.contains(&note);
+            if !sounding {
+                events.push(NoteEvent {
+                    start,
+                    end: frame.time,
+                    note,
+                    velocity,
+                });
+            }
+            sounding
+        });
+
+        for &note in &frame.notes {
+            active.entry(note).or_insert((frame.time, frame.velocity));
+        }
+    }
+
+    for (note, (start, velocity)) in active {
+        events.push(NoteEvent {
+            start,
+            end,
+            note,
+            velocity,
+        });
+    }
+
+    events.sort_by_key(|event| (event.start, event.note));
+    events
+}
+
+/// Writes a format 1 Standard MIDI File with a tempo track and one note track.
+pub fn write_midi(
+    events: &[NoteEvent],
+    options: MidiOptions,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    let ticks_per_second = options.tempo_bpm / 60.0 * TICKS_PER_QUARTER as f64;
+    let grid = options
+        .quantize
+        .map(|division| (TICKS_PER_QUARTER as u64 * 4 / division.max(1) as u64).max(1))
+        .unwrap_or(1);
+    let to_ticks = |time: Duration| {
+        let ticks = (time.as_secs_f64() * ticks_per_second).round() as u64;
+        (ticks as f64 / grid as f64).round() as u64 * grid
+    };
+
+    // (start, end, note, velocity) in ticks
+    let mut notes: Vec<(u64, u64, u8, u8)> = events
+        .iter()
+        .filter(|event| event.end.saturating_sub(event.start) >= options.min_duration)
+        .map(|event| {
+            let start = to_ticks(event.start);
+            // notes shorter than the grid keep one grid step
+            let end = to_ticks(event.end).max(start + grid);
+            (start, end, event.note, event.velocity)
+        })
+        .collect();
+
+    // snapping can make notes of the same pitch overlap, join those
+    notes.sort_by_key(|&(start, _, note, _)| (note, start));
+    let mut merged: Vec<(u64, u64, u8, u8)> = Vec::with_capacity(notes.len());
+    for note in notes {
+        match merged.last_mut() {
+            Some(last) if last.2 == note.2 && note.0 <= last.1 => last.1 = last.1.max(note.1),
+            _ => merged.push(note),
+        }
+    }
+
+    // (tick, is note on, note, velocity), note offs sort first on a tie
+    let mut messages: Vec<(u64, bool, u8, u8)> = Vec::with_capacity(merged.len() * 2);
+    for (start, end, note, velocity) in merged {
+        messages.push((start, true, note, velocity));
+        messages.push((end, false, note, 0));
+    }
+    messages.sort_by_key(|&(tick, is_on, note, _)| (tick, is_on, note));
+
+    // tempo track
+    let mut tempo_track = Vec::new();
+    let micros_per_quarter = (60_000_000.0 / options.tempo_bpm).round() as u32;
+    write_var_len(&mut tempo_track, 0);
+    tempo_track.extend([0xFF, 0x51, 0x03]);
+    tempo_track.extend(&micros_per_quarter.to_be_bytes()[1..]);
+    write_var_len(&mut tempo_track, 0);
+    tempo_track.extend([0xFF, 0x58, 0x04, 4, 2, 24, 8]); // 4/4
+    write_var_len(&mut tempo_track, 0);
+    tempo_track.extend([0xFF, 0x2F, 0x00]);
+
+    // note track
+    let mut note_track = Vec::new();
+    let name = b"transcription";
+    write_var_len(&mut note_track, 0);
+    note_track.extend([0xFF, 0x03, name.len() as u8]);
+    note_track.extend(name);
+
+    let mut last_tick = 0;
+    for (tick, is_on, note, velocity) in messages {
+        write_var_len(&mut note_track, (tick - last_tick) as u32);
+        last_tick = tick;
+        let status = if is_on { 0x90 } else { 0x80 };
+        note_track.extend([status, note, velocity]);
+    }
+    write_var_len(&mut note_track, 0);
+    note_track.extend([0xFF, 0x2F, 0x00]);
+
+    writer.write_all(b"MThd")?;
+    writer.write_all(&6u32.to_be_bytes())?;
+    writer.write_all(&1u16.to_be_bytes())?; // format 1
+    writer.write_all(&2u16.to_be_bytes())?; // tracks
+    writer.write_all(&TICKS_PER_QUARTER.to_be_bytes())?;
+    for track in [tempo_track, note_track] {
+        writer.write_all(b"MTrk")?;
+        writer.write_all(&(track.len() as u32).to_be_bytes())?;
+        writer.write_all(&track)?;
+    }
+    Ok(())
+}
+
+// midi variable-length quantity, 7 bits per byte, most significant first
+fn write_var_len(buffer: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.extend(bytes.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // middle c for half a second at 120 bpm, a quarter note of 480 ticks
+    fn one_note() -> Vec<u8> {
+        let events = [NoteEvent {
+            start: Duration::ZERO,
+            end: Duration::from_millis(500),
+            note: 60,
+            velocity: 100,
+        }];
+        let mut bytes = Vec::new();
+        write_midi(&events, MidiOptions::default(), &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn writes_the_header_chunk() {
+        let header = [
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, // length
+            0, 1, // format 1
+            0, 2, // tracks
+            0x01, 0xE0, // 480 ticks per quarter
+        ];
+        assert_eq!(one_note()[..14], header);
+    }
+
+    #[test]
+    fn writes_the_track_chunks() {
+        let mut tracks = vec![b'M', b'T', b'r', b'k', 0, 0, 0, 19];
+        tracks.extend([0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]); // 500000 µs per quarter
+        tracks.extend([0x00, 0xFF, 0x58, 0x04, 4, 2, 24, 8]); // 4/4
+        tracks.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+        tracks.extend([b'M', b'T', b'r', b'k', 0, 0, 0, 30]);
+        tracks.extend([0x00, 0xFF, 0x03, 13]);
+        tracks.extend(b"transcription");
+        tracks.extend([0x00, 0x90, 60, 100]);
+        tracks.extend([0x83, 0x60, 0x80, 60, 0]); // 480 ticks later
+        tracks.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+        assert_eq!(one_note()[14..], tracks);
+    }
+
+    #[test]
+    fn writes_variable_length_quantities() {
+        for (value, expected) in [
+            (0, vec![0x00]),
+            (0x7F, vec![0x7F]),
+            (0x80, vec![0x81, 0x00]),
+            (0x3FFF, vec![0xFF, 0x7F]),
+            (0x0FFF_FFFF, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+        ] {
+            let mut buffer = Vec::new();
+            write_var_len(&mut buffer, value);
+            assert_eq!(buffer, expected, "{:#X}", value);
+        }
+    }
+}
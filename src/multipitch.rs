@@ -0,0 +1,272 @@
+use crate::fft::parabolic_offset;
+use crate::notes::{Chord, Pitch, match_chord};
+use crate::window::StftConfig;
+
+// candidate fundamentals in MIDI notes, A1 to C7
+const MIN_NOTE: u8 = 33;
+const MAX_NOTE: u8 = 96;
+// partials summed per candidate
+const HARMONICS: usize = 10;
+// how far a partial may sit from its ideal frequency, about half a semitone
+const PARTIAL_TOLERANCE: f32 = 0.03;
+// weight of partial h of f0 is (f0 + ALPHA) / (h * f0 + BETA), after Klapuri
+const ALPHA: f32 = 27.0;
+const BETA: f32 = 320.0;
+// most notes reported at once
+const MAX_POLYPHONY: usize = 6;
+// notes weaker than this fraction of the strongest are left out
+const MIN_RELATIVE_SALIENCE: f32 = 0.5;
+// spectra whose loudest bin is below this are silence
+const MIN_MAGNITUDE: f32 = 1e-3;
+
+/// A pitch heard in a frame, with how strongly it stands out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchSalience {
+    pub pitch: Pitch,
+    /// 0 to 1, relative to the strongest pitch of the frame
+    pub salience: f32,
+}
+
+/// Estimates the notes sounding at once in a magnitude spectrum, strongest
+/// first. Each round takes the candidate whose harmonics sum highest and
+/// subtracts a smoothed copy of those harmonics, so that partials shared
+/// with other notes are left for them.
+pub fn estimate_pitches(
+    magnitudes: &[f32],
+    sample_rate: f32,
+    stft: &StftConfig,
+) -> Vec<PitchSalience> {
+    let max = magnitudes.iter().cloned().fold(0.0, f32::max);
+    if max < MIN_MAGNITUDE {
+        return Vec::new();
+    }
+    let bin_hz = sample_rate / stft.fft_size as f32;
+    // bins per bin of the unpadded window, how much wider peaks are
+    let padding = (stft.fft_size / stft.window_size).max(1);
+
+    // compress so a loud fundamental doesn't hide quieter notes
+    let mut residual: Vec<f32> = magnitudes
+        .iter()
+        .map(|magnitude| (1.0 + 100.0 * magnitude / max).ln())
+        .collect();
+    // with zero-padding a bin next to a peak is mostly that peak, so only
+    // peaks count in full. they are found before compressing, which
+    // flattens the tops of loud ones
+    let flank_weight = 1.0 / padding as f32;
+    let bin_weights: Vec<f32> = (0..magnitudes.len())
+        .map(|bin| {
+            let peak = bin > 0
+                && bin + 1 < magnitudes.len()
+                && magnitudes[bin] > magnitudes[bin - 1]
+                && magnitudes[bin] >= magnitudes[bin + 1];
+            if peak { 1.0 } else { flank_weight }
+        })
+        .collect();
+
+    let mut found: Vec<(f32, f32)> = Vec::new();
+    while found.len() < MAX_POLYPHONY {
+        let mut best: Option<(u8, f32)> = None;
+        for note in MIN_NOTE..=MAX_NOTE {
+            if found
+                .iter()
+                .any(|(frequency, _)| nearest_note(*frequency) == note)
+            {
+                continue;
+            }
+            let salience = salience(&residual, &bin_weights, note_frequency(note), bin_hz);
+            if best.is_none_or(|(_, best_salience)| salience > best_salience) {
+                best = Some((note, salience));
+            }
+        }
+
+        let Some((note, salience)) = best else {
+            break;
+        };
+        let first_salience = found.first().map_or(salience, |(_, salience)| *salience);
+        if salience <= 0.0 || salience < first_salience * MIN_RELATIVE_SALIENCE {
+            break;
+        }
+
+        let partials = partials(&residual, &bin_weights, note_frequency(note), bin_hz);
+        let frequency =
+            refine_frequency(&residual, &partials, bin_hz).unwrap_or_else(|| note_frequency(note));
+        subtract_partials(&mut residual, &partials, padding);
+        found.push((frequency, salience));
+    }
+
+    let strongest = found.first().map_or(1.0, |(_, salience)| *salience);
+    found
+        .into_iter()
+        .filter_map(|(frequency, salience)| {
+            Some(PitchSalience {
+                pitch: Pitch::from_frequency(frequency)?,
+                salience: salience / strongest,
+            })
+        })
+        .collect()
+}
+
+/// Chord of the pitches of a frame, the lowest one as its bass.
+pub fn detect_chord(pitches: &[PitchSalience]) -> Option<Chord> {
+    let mut chroma = [0.0; 12];
+    for pitch in pitches {
+        chroma[pitch.pitch.pitch_class as usize] += pitch.salience;
+    }
+    let bass = pitches
+        .iter()
+        .min_by(|a, b| a.pitch.frequency.total_cmp(&b.pitch.frequency))
+        .map(|lowest| lowest.pitch.pitch_class);
+    match_chord(&chroma, bass)
+}
+
+fn note_frequency(note: u8) -> f32 {
+    440.0 * 2f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+fn nearest_note(frequency: f32) -> u8 {
+    (69.0 + 12.0 * (frequency / 440.0).log2()).round() as u8
+}
+
+fn partial_weight(fundamental: f32, harmonic: usize) -> f32 {
+    (fundamental + ALPHA) / (harmonic as f32 * fundamental + BETA)
+}
+
+// strongest bin near each harmonic of `fundamental`, with its harmonic
+// number, preferring peaks over the flanks of neighbouring ones
+fn partials(
+    spectrum: &[f32],
+    bin_weights: &[f32],
+    fundamental: f32,
+    bin_hz: f32,
+) -> Vec<(usize, usize)> {
+    let mut partials = Vec::with_capacity(HARMONICS);
+    for harmonic in 1..=HARMONICS {
+        let ideal = fundamental * harmonic as f32;
+        let low = (ideal * (1.0 - PARTIAL_TOLERANCE) / bin_hz).round() as usize;
+        let high = (ideal * (1.0 + PARTIAL_TOLERANCE) / bin_hz).round() as usize;
+        if high >= spectrum.len() {
+            break;
+        }
+        let bin = (low.max(1)..=high.max(1))
+            .max_by(|a, b| {
+                (spectrum[*a] * bin_weights[*a]).total_cmp(&(spectrum[*b] * bin_weights[*b]))
+            })
+            .unwrap_or(low);
+        partials.push((harmonic, bin));
+    }
+    partials
+}
+
+// weighted sum of the partials of `fundamental`
+fn salience(spectrum: &[f32], bin_weights: &[f32], fundamental: f32, bin_hz: f32) -> f32 {
+    partials(spectrum, bin_weights, fundamental, bin_hz)
+        .iter()
+        .map(|&(harmonic, bin)| {
+            partial_weight(fundamental, harmonic) * spectrum[bin] * bin_weights[bin]
+        })
+        .sum()
+}
+
+// fundamental from the first few partials, each placed between bins with a
+// parabola and divided by its harmonic number
+fn refine_frequency(spectrum: &[f32], partials: &[(usize, usize)], bin_hz: f32) -> Option<f32> {
+    let (mut total, mut weights) = (0.0, 0.0);
+    for &(harmonic, bin) in partials.iter().take(4) {
+        if bin == 0 || bin + 1 >= spectrum.len() {
+            continue;
+        }
+        let (left, center, right) = (spectrum[bin - 1], spectrum[bin], spectrum[bin + 1]);
+        // only actual peaks say where the partial is
+        if center <= left || center < right {
+            continue;
+        }
+        let offset = parabolic_offset(spectrum, bin);
+        total += center * (bin as f32 + offset) * bin_hz / harmonic as f32;
+        weights += center;
+    }
+    (weights > 0.0).then(|| total / weights)
+}
+
+// removes the amplitudes a smooth harmonic series would have, which keeps
+// the part of louder partials that belongs to other notes
+fn subtract_partials(spectrum: &mut [f32], partials: &[(usize, usize)], padding: usize) {
+    let amplitudes: Vec<f32> = partials.iter().map(|&(_, bin)| spectrum[bin]).collect();
+    for (i, &(_, bin)) in partials.iter().enumerate() {
+        let neighbours = &amplitudes[i.saturating_sub(1)..(i + 2).min(amplitudes.len())];
+        let smooth = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+        let amount = amplitudes[i].min(smooth);
+
+        // a zero-padded fft spreads the same peak over more bins
+        let low = bin.saturating_sub(padding);
+        let high = (bin + padding).min(spectrum.len() - 1);
+        for value in &mut spectrum[low..=high] {
+            *value = (*value - amount).max(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::SpectrumAnalyzer;
+    use crate::window::WindowKind;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    // notes with five harmonics each, falling off as 1/h
+    fn harmonic_spectrum(notes: &[u8], stft: &StftConfig) -> Vec<f32> {
+        let samples: Vec<f32> = (0..stft.window_size)
+            .map(|i| {
+                let time = i as f32 / SAMPLE_RATE;
+                notes
+                    .iter()
+                    .flat_map(|&note| {
+                        (1..=5).map(move |harmonic| {
+                            let frequency = note_frequency(note) * harmonic as f32;
+                            0.2 * (2.0 * PI * frequency * time).sin() / harmonic as f32
+                        })
+                    })
+                    .sum()
+            })
+            .collect();
+        let mut analyzer = SpectrumAnalyzer::from_stft(stft);
+        analyzer.process(&samples).unwrap();
+        analyzer.magnitudes().to_vec()
+    }
+
+    fn midi_notes(pitches: &[PitchSalience]) -> Vec<u8> {
+        let mut notes: Vec<u8> = pitches.iter().map(|pitch| pitch.pitch.midi).collect();
+        notes.sort_unstable();
+        notes
+    }
+
+    #[test]
+    fn finds_both_notes_of_a_fifth_without_their_harmonics() {
+        // c4 and g4, whose octaves and twelfths land on c5, g5 and d6
+        for stft in [
+            StftConfig::new(4096, WindowKind::Hann),
+            StftConfig::new(4096, WindowKind::Hann).with_fft_size(8192),
+        ] {
+            let spectrum = harmonic_spectrum(&[60, 67], &stft);
+            let pitches = estimate_pitches(&spectrum, SAMPLE_RATE, &stft);
+            assert_eq!(midi_notes(&pitches), [60, 67], "{:?}", stft);
+            for pitch in &pitches {
+                assert!(pitch.pitch.cents.abs() < 10.0, "{}", pitch.pitch);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_one_note_without_its_octave() {
+        let stft = StftConfig::new(4096, WindowKind::Hann);
+        let pitches = estimate_pitches(&harmonic_spectrum(&[57], &stft), SAMPLE_RATE, &stft);
+        assert_eq!(midi_notes(&pitches), [57]);
+    }
+
+    #[test]
+    fn silence_has_no_pitches() {
+        let stft = StftConfig::new(4096, WindowKind::Hann);
+        assert!(estimate_pitches(&vec![0.0; 2049], SAMPLE_RATE, &stft).is_empty());
+    }
+}
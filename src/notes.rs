@@ -1,92 +1,349 @@
-use lazy_static::lazy_static;
-use std::collections::HashMap;
-
-lazy_static! {
-    static ref CHORD_DATABASE: HashMap<&'static str, Vec<&'static str>> = {
-    let mut chords = HashMap::new();
-
-    // major chords
-    chords.insert("C", vec!["C", "E", "G"]);
-    chords.insert("C#", vec!["C#", "F", "G#"]);
-    chords.insert("D", vec!["D", "F#", "A"]);
-    chords.insert("D#", vec!["D#", "G", "A#"]);
-    chords.insert("E", vec!["E", "G#", "B"]);
-    chords.insert("F", vec!["F", "A", "C"]);
-    chords.insert("F#", vec!["F#", "A#", "C#"]);
-    chords.insert("G", vec!["G", "B", "D"]);
-    chords.insert("G#", vec!["G#", "C", "D#"]);
-    chords.insert("A", vec!["A", "C#", "E"]);
-    chords.insert("A#", vec!["A#", "D", "F"]);
-    chords.insert("B", vec!["B", "D#", "F#"]);
-
-    // minor chords
-    chords.insert("Cm", vec!["C", "D#", "G"]);
-    chords.insert("C#m", vec!["C#", "E", "G#"]);
-    chords.insert("Dm", vec!["D", "F", "A"]);
-    chords.insert("D#m", vec!["D#", "F#", "A#"]);
-    chords.insert("Em", vec!["E", "G", "B"]);
-    chords.insert("Fm", vec!["F", "G#", "C"]);
-    chords.insert("F#m", vec!["F#", "A", "C#"]);
-    chords.insert("Gm", vec!["G", "A#", "D"]);
-    chords.insert("G#m", vec!["G#", "B", "D#"]);
-    chords.insert("Am", vec!["A", "C", "E"]);
-    chords.insert("A#m", vec!["A#", "C#", "F"]);
-    chords.insert("Bm", vec!["B", "D", "F#"]);
-
-    chords
-    };
+use std::fmt;
+
+use crate::fft::SpectralPeak;
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Name of a pitch class, 0 being C.
+pub fn pitch_class_name(pitch_class: u8) -> &'static str {
+    NOTE_NAMES[pitch_class as usize % 12]
 }
 
-// takes in top frequencies from a window and searches for chord
-// otherwise it will choose the dominant frequency
-pub fn frequency_to_note(frequencies: &[f32]) -> String {
-    if frequencies.is_empty() {
-        return "N/A".to_string();
-    }
+/// A frequency placed on the equal-tempered scale, A4 = 440Hz.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pitch {
+    /// nearest MIDI note number
+    pub midi: u8,
+    pub octave: i32,
+    /// 0 to 11, 0 being C
+    pub pitch_class: u8,
+    /// distance from `midi`, -50 to +50
+    pub cents: f32,
+    pub frequency: f32,
+}
 
-    let mut detected_notes: Vec<String> = Vec::new();
-    for &frequency in frequencies {
+impl Pitch {
+    /// `None` below 20Hz or outside the MIDI range.
+    pub fn from_frequency(frequency: f32) -> Option<Self> {
         if frequency < 20.0 {
-            continue;
+            return None;
         }
+        // A4 = 440Hz = MIDI note 69
         let midi_note = 69.0 + 12.0 * (frequency / 440.0).log2();
-        let rounded_midi = midi_note.round() as i32;
+        let rounded_midi = midi_note.round();
+        if rounded_midi > 127.0 {
+            return None;
+        }
+        let midi = rounded_midi as u8;
 
-        let note_names = [
-            "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
-        ];
-        let note_index = (rounded_midi % 12) as usize;
+        Some(Self {
+            midi,
+            octave: (midi / 12) as i32 - 1,
+            pitch_class: midi % 12,
+            cents: (midi_note - rounded_midi) * 100.0,
+            frequency,
+        })
+    }
 
-        detected_notes.push(note_names[note_index].to_string());
+    pub fn name(&self) -> &'static str {
+        pitch_class_name(self.pitch_class)
     }
+}
 
-    if detected_notes.is_empty() {
-        return "N/A".to_string();
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{} ({:+}¢)",
+            self.name(),
+            self.octave,
+            self.cents.round() as i32
+        )
     }
+}
 
-    detected_notes.dedup();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Sixth,
+    Add9,
+    Dominant7,
+    Major7,
+    Minor7,
+    HalfDiminished7,
+    Diminished7,
+}
 
-    for (chord_name, chord_notes) in CHORD_DATABASE.iter() {
-        let chord_match = chord_notes
-            .iter()
-            .all(|&chord_note| detected_notes.iter().any(|detected| detected == chord_note));
+impl ChordQuality {
+    // simpler chords first, so they win ties
+    const ALL: [ChordQuality; 13] = [
+        ChordQuality::Major,
+        ChordQuality::Minor,
+        ChordQuality::Diminished,
+        ChordQuality::Augmented,
+        ChordQuality::Sus2,
+        ChordQuality::Sus4,
+        ChordQuality::Sixth,
+        ChordQuality::Add9,
+        ChordQuality::Dominant7,
+        ChordQuality::Major7,
+        ChordQuality::Minor7,
+        ChordQuality::HalfDiminished7,
+        ChordQuality::Diminished7,
+    ];
 
-        if chord_match {
-            return format!("{} chord", chord_name);
+    /// Semitones above the root, within one octave.
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Diminished => &[0, 3, 6],
+            ChordQuality::Augmented => &[0, 4, 8],
+            ChordQuality::Sus2 => &[0, 2, 7],
+            ChordQuality::Sus4 => &[0, 5, 7],
+            ChordQuality::Sixth => &[0, 4, 7, 9],
+            ChordQuality::Add9 => &[0, 2, 4, 7],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Major7 => &[0, 4, 7, 11],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+            ChordQuality::HalfDiminished7 => &[0, 3, 6, 10],
+            ChordQuality::Diminished7 => &[0, 3, 6, 9],
         }
     }
 
-    // A4 = 440Hz = MIDI note 69
-    let midi_note = 69.0 + 12.0 * (frequencies[0] / 440.0).log2();
-    let rounded_midi = midi_note.round() as i32;
+    /// Suffix after the root in a chord symbol.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Diminished => "dim",
+            ChordQuality::Augmented => "aug",
+            ChordQuality::Sus2 => "sus2",
+            ChordQuality::Sus4 => "sus4",
+            ChordQuality::Sixth => "6",
+            ChordQuality::Add9 => "add9",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Major7 => "maj7",
+            ChordQuality::Minor7 => "m7",
+            ChordQuality::HalfDiminished7 => "m7b5",
+            ChordQuality::Diminished7 => "dim7",
+        }
+    }
 
-    let note_names = [
-        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
-    ];
-    let octave = (rounded_midi / 12) - 1;
-    let note_index = (rounded_midi % 12) as usize;
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "major",
+            ChordQuality::Minor => "minor",
+            ChordQuality::Diminished => "diminished",
+            ChordQuality::Augmented => "augmented",
+            ChordQuality::Sus2 => "sus2",
+            ChordQuality::Sus4 => "sus4",
+            ChordQuality::Sixth => "sixth",
+            ChordQuality::Add9 => "add9",
+            ChordQuality::Dominant7 => "dominant seventh",
+            ChordQuality::Major7 => "major seventh",
+            ChordQuality::Minor7 => "minor seventh",
+            ChordQuality::HalfDiminished7 => "half-diminished seventh",
+            ChordQuality::Diminished7 => "diminished seventh",
+        }
+    }
+
+    // 1 for every pitch class in the chord built on `root`
+    fn template(&self, root: u8) -> [f32; 12] {
+        let mut template = [0.0; 12];
+        for interval in self.intervals() {
+            template[((root + interval) % 12) as usize] = 1.0;
+        }
+        template
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chord {
+    /// pitch class of the root
+    pub root: u8,
+    pub quality: ChordQuality,
+    /// pitch class of the lowest note, if it is not the root
+    pub bass: Option<u8>,
+    /// 0 to 1, similarity of the chord to what was heard
+    pub confidence: f32,
+}
+
+impl Chord {
+    /// Whether `pitch_class` is one of the chord's notes.
+    pub fn contains(&self, pitch_class: u8) -> bool {
+        self.quality.template(self.root)[pitch_class as usize % 12] > 0.0
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            pitch_class_name(self.root),
+            self.quality.symbol()
+        )?;
+        if let Some(bass) = self.bass {
+            write!(f, "/{}", pitch_class_name(bass))?;
+        }
+        write!(f, " chord")
+    }
+}
+
+/// What was heard in one frequency band.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BandResult {
+    /// strongest first
+    pub pitches: Vec<Pitch>,
+    pub chord: Option<Chord>,
+}
 
-    let cents_off = ((midi_note - rounded_midi as f32) * 100.0).round() as i32;
+impl BandResult {
+    /// The strongest pitch in the band.
+    pub fn pitch(&self) -> Option<&Pitch> {
+        self.pitches.first()
+    }
+}
+
+impl fmt::Display for BandResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.chord, self.pitch()) {
+            (Some(chord), _) => write!(f, "{}", chord),
+            (None, Some(pitch)) => write!(f, "{}", pitch),
+            (None, None) => write!(f, "N/A"),
+        }
+    }
+}
+
+// matches below this cosine similarity are not reported as chords,
+// it rejects two notes of a triad but keeps three notes of a seventh
+const MIN_CHORD_SCORE: f32 = 0.85;
+// enough to break ties between chords sharing all notes, e.g. C6 and Am7
+const BASS_ROOT_BONUS: f32 = 0.01;
+
+// takes in top peaks from a band, strongest first. a band's few peaks
+// rarely hold a whole chord, so it reports the chord of the frame's notes,
+// over the frame's lowest one, when its strongest note is part of it.
+// otherwise the dominant frequency is the result
+pub fn detect_band(peaks: &[SpectralPeak], frame_chord: Option<Chord>) -> BandResult {
+    let pitches: Vec<Pitch> = peaks
+        .iter()
+        .filter_map(|peak| Pitch::from_frequency(peak.frequency))
+        .collect();
 
-    format!("{}{} ({:+}¢)", note_names[note_index], octave, cents_off)
+    let chord = frame_chord.filter(|chord| {
+        pitches
+            .first()
+            .is_some_and(|pitch| chord.contains(pitch.pitch_class))
+    });
+    BandResult { pitches, chord }
+}
+
+/// Scores every chord template against `chroma` and returns the best one,
+/// if it is close enough. The chord is written over `bass` when that is
+/// one of its notes other than the root.
+pub fn match_chord(chroma: &[f32; 12], bass: Option<u8>) -> Option<Chord> {
+    let norm = chroma.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm <= f32::EPSILON {
+        return None;
+    }
+
+    let mut best: Option<(f32, Chord)> = None;
+    for quality in ChordQuality::ALL {
+        for root in 0..12 {
+            let template = quality.template(root);
+            // in pitch class order so chords with the same notes score the same
+            let dot: f32 = chroma.iter().zip(&template).map(|(c, t)| c * t).sum();
+            let similarity = dot / (norm * (quality.intervals().len() as f32).sqrt());
+
+            let score = if bass == Some(root) {
+                similarity + BASS_ROOT_BONUS
+            } else {
+                similarity
+            };
+            if best.is_some_and(|(best_score, _)| score <= best_score) {
+                continue;
+            }
+
+            let chord_bass = bass.filter(|&bass| bass != root && template[bass as usize] > 0.0);
+            best = Some((
+                score,
+                Chord {
+                    root,
+                    quality,
+                    bass: chord_bass,
+                    confidence: similarity.clamp(0.0, 1.0),
+                },
+            ));
+        }
+    }
+
+    best.map(|(_, chord)| chord)
+        .filter(|chord| chord.confidence >= MIN_CHORD_SCORE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // equal weight on each of `pitch_classes`
+    fn chroma(pitch_classes: &[u8]) -> [f32; 12] {
+        let mut chroma = [0.0; 12];
+        for &pitch_class in pitch_classes {
+            chroma[pitch_class as usize] = 1.0;
+        }
+        chroma
+    }
+
+    #[test]
+    fn recognizes_a_major_triad() {
+        let chord = match_chord(&chroma(&[0, 4, 7]), Some(0)).unwrap();
+        assert_eq!(
+            (chord.root, chord.quality, chord.bass),
+            (0, ChordQuality::Major, None)
+        );
+        assert!(chord.confidence > 0.99);
+    }
+
+    #[test]
+    fn recognizes_a_minor_triad() {
+        let chord = match_chord(&chroma(&[9, 0, 4]), Some(9)).unwrap();
+        assert_eq!(
+            (chord.root, chord.quality, chord.bass),
+            (9, ChordQuality::Minor, None)
+        );
+    }
+
+    #[test]
+    fn writes_an_inversion_over_its_bass() {
+        // C major with E in the bass
+        let chord = match_chord(&chroma(&[4, 7, 0]), Some(4)).unwrap();
+        assert_eq!(
+            (chord.root, chord.quality, chord.bass),
+            (0, ChordQuality::Major, Some(4))
+        );
+        assert_eq!(chord.to_string(), "C/E chord");
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_chord() {
+        // a single note, two notes of a triad and a cluster
+        for pitch_classes in [&[0][..], &[0, 7], &[0, 1, 2]] {
+            assert_eq!(
+                match_chord(&chroma(pitch_classes), None),
+                None,
+                "{:?}",
+                pitch_classes
+            );
+        }
+        assert_eq!(match_chord(&[0.0; 12], None), None);
+    }
 }
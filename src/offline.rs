@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use crate::analyzer::PitchStrategy;
+use crate::aux::AnalysisResult;
+use crate::beat::{OnsetDetector, estimate_tempo, track_beats};
+use crate::channels::ChannelMode;
+use crate::chroma::spectrum_chroma;
+use crate::fft::SpectrumAnalyzer;
+use crate::key::{Key, KeyTracker, estimate_key};
+use crate::multipitch::{detect_chord, estimate_pitches};
+use crate::notes::detect_band;
+use crate::source::AudioSource;
+use crate::window::StftConfig;
+use crate::yin::Yin;
+
+/// Everything an offline run produced.
+pub struct OfflineAnalysis {
+    /// the analyzed channel signal
+    pub samples: Vec<f32>,
+    pub sample_rate: f32,
+    pub results: Vec<AnalysisResult>,
+    /// per window, the rms level of the signal
+    pub levels: Vec<f32>,
+    /// key of the whole signal
+    pub key: Option<Key>,
+    /// tempo of the whole signal in BPM
+    pub tempo: Option<f32>,
+    pub beats: Vec<Duration>,
+}
+
+impl OfflineAnalysis {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / self.sample_rate as f64)
+    }
+}
+
+/// Reads all of `source` and detects notes in every window, as fast as
+/// the machine allows instead of at playback pace.
+pub fn analyze_source(
+    mut source: Box<dyn AudioSource>,
+    channel_mode: ChannelMode,
+    strategy: PitchStrategy,
+    stft: StftConfig,
+) -> Result<OfflineAnalysis, Box<dyn std::error::Error>> {
+    let sample_rate = source.sample_rate();
+    let channels = source.channels();
+    channel_mode.validate(channels)?;
+    stft.validate()?;
+
+    // read in one second blocks
+    let block_frames = sample_rate as usize;
+    let mut samples = Vec::new();
+    loop {
+        let block = source
+            .read(block_frames)
+            .map_err(|err| err as Box<dyn std::error::Error>)?;
+        if block.is_empty() {
+            break;
+        }
+        samples.extend(channel_mode.extract(&block, channels));
+    }
+
+    let window_size = stft.window_size;
+    let mut results = Vec::new();
+    let mut levels = Vec::new();
+    let mut key_tracker = KeyTracker::default();
+    // louder windows count more towards the key of the whole signal
+    let mut total_chroma = [0.0; 12];
+    let mut onset_detector = OnsetDetector::default();
+    let mut onsets = Vec::new();
+    let mut yin = Yin::new(sample_rate, window_size);
+    let mut spectrum = SpectrumAnalyzer::from_stft(&stft);
+
+    for position in stft.window_starts(samples.len()) {
+        let window = &samples[position..position + window_size];
+        spectrum.process(window)?;
+        let magnitudes = spectrum.magnitudes();
+        let rms = (window.iter().map(|s| s * s).sum::<f32>() / window_size as f32).sqrt();
+
+        let timestamp = Duration::from_secs_f64(position as f64 / sample_rate as f64);
+        let chroma = spectrum_chroma(magnitudes, sample_rate, stft.fft_size);
+        onsets.push(onset_detector.strength(magnitudes));
+        let pitches = estimate_pitches(magnitudes, sample_rate, &stft);
+        for (total, value) in total_chroma.iter_mut().zip(&chroma) {
+            *total += value * rms;
+        }
+
+        let chord = detect_chord(&pitches);
+        results.push(AnalysisResult {
+            timestamp,
+            bands: match strategy {
+                PitchStrategy::Bands => spectrum
+                    .bands(sample_rate, 3)
+                    .iter()
+                    .map(|band| detect_band(band, chord))
+                    .collect(),
+                PitchStrategy::Yin => vec![yin.detect_band(window)],
+            },
+            chord,
+            pitches,
+            chroma,
+            key: key_tracker.update(&chroma, timestamp),
+            tempo: None,
+            beats: Vec::new(),
+        });
+        levels.push(rms);
+    }
+
+    // tempo and beats need the whole signal, one onset per result
+    let frame_rate = stft.frame_rate(sample_rate);
+    let tempo = estimate_tempo(&onsets, frame_rate);
+    let mut beats = Vec::new();
+    if let Some(bpm) = tempo {
+        // an onset peaks when it reaches the middle of the window
+        let half_window = Duration::from_secs_f64(window_size as f64 / 2.0 / sample_rate as f64);
+        for frame in track_beats(&onsets, frame_rate, bpm) {
+            let result = &mut results[frame];
+            result.beats.push(result.timestamp + half_window);
+            beats.push(result.timestamp + half_window);
+        }
+    }
+    for result in results.iter_mut() {
+        result.tempo = tempo;
+    }
+
+    Ok(OfflineAnalysis {
+        samples,
+        sample_rate,
+        results,
+        levels,
+        key: estimate_key(&total_chroma),
+        tempo,
+        beats,
+    })
+}
@@ -1,7 +1,10 @@
 use plotters::prelude::*;
 
-pub fn plot_waveform(samples: &[f32], sample_rate: f32) -> Result<(), Box<dyn std::error::Error>> {
-    let output_path = "waveform.png";
+pub fn plot_waveform(
+    samples: &[f32],
+    sample_rate: f32,
+    output_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
     let root = BitMapBackend::new(output_path, (1200, 600)).into_drawing_area();
     root.fill(&WHITE)?;
 
@@ -49,11 +52,12 @@ pub fn plot_waveform(samples: &[f32], sample_rate: f32) -> Result<(), Box<dyn st
     chart
         .draw_series(LineSeries::new(plot_samples, &BLUE))?
         .label("Waveform")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], &BLUE));
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], BLUE));
 
     chart.configure_series_labels().draw()?;
     root.present()?;
 
-    println!("Waveform saved as {}", output_path);
+    // stdout may be carrying exported results
+    eprintln!("Waveform saved as {}", output_path);
     Ok(())
 }
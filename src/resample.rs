@@ -0,0 +1,231 @@
+use std::f64::consts::PI;
+
+// zero crossings of the sinc kernel on each side of the output sample
+const HALF_TAPS: usize = 16;
+// number of precomputed fractional offsets between two input frames
+const PHASES: usize = 256;
+
+/// Band-limited windowed-sinc resampler for interleaved audio.
+///
+/// The kernel is precomputed as a polyphase table and interpolated
+/// linearly between adjacent phases. State is kept between calls to
+/// `process` so chunks can be fed in one at a time.
+pub struct Resampler {
+    input_rate: f32,
+    output_rate: f32,
+    channels: usize,
+
+    // input frames advanced per output frame
+    step: f64,
+    // (PHASES + 1) rows of 2 * HALF_TAPS coefficients
+    table: Vec<f32>,
+
+    // interleaved input that is still needed by the kernel
+    history: Vec<f32>,
+    // position of the next output frame, in frames relative to history start
+    time: f64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: f32, output_rate: f32, channels: usize) -> Self {
+        let step = input_rate as f64 / output_rate as f64;
+
+        // lowpass below the lower of the two nyquist frequencies
+        let cutoff = (1.0 / step).min(1.0) * 0.95;
+        let taps = 2 * HALF_TAPS;
+        let mut table = vec![0.0f32; (PHASES + 1) * taps];
+
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            let row = &mut table[phase * taps..(phase + 1) * taps];
+
+            for (j, coeff) in row.iter_mut().enumerate() {
+                // distance between the output position and this tap
+                let distance = frac + (HALF_TAPS - 1) as f64 - j as f64;
+                *coeff = (cutoff * sinc(cutoff * distance) * blackman(distance)) as f32;
+            }
+
+            // normalize for unity gain at dc
+            let sum: f32 = row.iter().sum();
+            if sum.abs() > f32::EPSILON {
+                row.iter_mut().for_each(|coeff| *coeff /= sum);
+            }
+        }
+
+        let mut resampler = Self {
+            input_rate,
+            output_rate,
+            channels,
+            step,
+            table,
+            history: Vec::new(),
+            time: 0.0,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.input_rate == self.output_rate
+    }
+
+    /// Drops buffered input, e.g. after a seek.
+    pub fn reset(&mut self) {
+        // prime with silence so the first output frame lines up with
+        // the first input frame
+        self.history = vec![0.0; (HALF_TAPS - 1) * self.channels];
+        self.time = (HALF_TAPS - 1) as f64;
+    }
+
+    /// Pushes out the input the kernel is still holding back, e.g. at the
+    /// end of a stream, and starts over.
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        if self.is_passthrough() {
+            return;
+        }
+
+        let silence = vec![0.0; HALF_TAPS * self.channels];
+        self.process(&silence, output);
+        self.reset();
+    }
+
+    /// Resamples a chunk of interleaved input and appends the result to `output`.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        if self.is_passthrough() {
+            output.extend_from_slice(input);
+            return;
+        }
+
+        let channels = self.channels;
+        let taps = 2 * HALF_TAPS;
+        self.history.extend_from_slice(input);
+        let history_frames = self.history.len() / channels;
+
+        // produce frames while the full kernel support is buffered
+        while (self.time as usize) + HALF_TAPS < history_frames {
+            let base = self.time as usize;
+            let frac = self.time - base as f64;
+
+            let phase_pos = frac * PHASES as f64;
+            let phase = phase_pos as usize;
+            let blend = (phase_pos - phase as f64) as f32;
+            let row_a = &self.table[phase * taps..(phase + 1) * taps];
+            let row_b = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+
+            let first_frame = base + 1 - HALF_TAPS;
+            for ch in 0..channels {
+                let mut acc = 0.0f32;
+                for j in 0..taps {
+                    let coeff = row_a[j] + (row_b[j] - row_a[j]) * blend;
+                    acc += self.history[(first_frame + j) * channels + ch] * coeff;
+                }
+                output.push(acc);
+            }
+
+            self.time += self.step;
+        }
+
+        // drop frames the kernel will no longer reach
+        let consumed = (self.time as usize + 1).saturating_sub(HALF_TAPS);
+        let consumed = consumed.min(history_frames);
+        self.history.drain(..consumed * channels);
+        self.time -= consumed as f64;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// blackman window spanning [-HALF_TAPS, HALF_TAPS]
+fn blackman(distance: f64) -> f64 {
+    let x = distance / HALF_TAPS as f64;
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // feeds `input` in chunks of `chunk_frames`, then flushes
+    fn resample(resampler: &mut Resampler, input: &[f32], chunk_frames: usize) -> Vec<f32> {
+        let mut output = Vec::new();
+        for chunk in input.chunks(chunk_frames * resampler.channels) {
+            resampler.process(chunk, &mut output);
+        }
+        resampler.flush(&mut output);
+        output
+    }
+
+    // from the rising zero crossings, skipping the first and last
+    fn zero_crossing_frequency(signal: &[f32], sample_rate: f32) -> f64 {
+        let crossings: Vec<f64> = signal
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(i, pair)| i as f64 + (pair[0] / (pair[0] - pair[1])) as f64)
+            .collect();
+        let (first, last) = (crossings[1], crossings[crossings.len() - 2]);
+        (crossings.len() - 3) as f64 * sample_rate as f64 / (last - first)
+    }
+
+    #[test]
+    fn tone_keeps_its_frequency() {
+        for (input_rate, output_rate) in [(44100.0, 48000.0), (48000.0, 44100.0)] {
+            let input: Vec<f32> = (0..input_rate as usize)
+                .map(|i| 0.5 * (2.0 * PI * 1000.0 * i as f64 / input_rate as f64).sin() as f32)
+                .collect();
+            let mut resampler = Resampler::new(input_rate, output_rate, 1);
+            let output = resample(&mut resampler, &input, 512);
+
+            // one second in, one second out
+            assert!((output.len() as f32 - output_rate).abs() <= HALF_TAPS as f32);
+            let frequency = zero_crossing_frequency(&output, output_rate);
+            assert!(
+                (frequency - 1000.0).abs() < 0.1,
+                "{} -> {}: {} Hz",
+                input_rate,
+                output_rate,
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn frames_stay_in_file_time() {
+        let (input_rate, output_rate) = (44100.0, 48000.0);
+        // each frame holds its own time, negated on the right
+        let input: Vec<f32> = (0..input_rate as usize)
+            .flat_map(|i| {
+                let time = i as f32 / input_rate;
+                [time, -time]
+            })
+            .collect();
+        let mut resampler = Resampler::new(input_rate, output_rate, 2);
+        let output = resample(&mut resampler, &input, 333);
+
+        // away from the edges, where the kernel reaches past the ramp
+        let edge = 2 * HALF_TAPS;
+        let frames = output.len() / 2;
+        for frame in edge..frames - edge {
+            let time = frame as f32 / output_rate;
+            let (left, right) = (output[2 * frame], output[2 * frame + 1]);
+            assert!((left - time).abs() < 1e-5, "frame {}: {} s", frame, left);
+            assert!((right + time).abs() < 1e-5, "frame {}: {} s", frame, right);
+        }
+    }
+
+    #[test]
+    fn same_rate_passes_through() {
+        let mut resampler = Resampler::new(44100.0, 44100.0, 2);
+        let input = [0.1, -0.1, 0.2, -0.2, 0.3, -0.3];
+        assert_eq!(resample(&mut resampler, &input, 2), input);
+    }
+}
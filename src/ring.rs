@@ -0,0 +1,167 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Creates a wait-free single-producer single-consumer ring of samples.
+///
+/// Neither side locks or allocates, so the consumer can live on the
+/// real-time audio thread.
+pub fn ring_buffer(capacity: usize) -> (RingProducer, RingConsumer) {
+    let shared = Arc::new(Shared {
+        // f32 bits, so slots can be shared without unsafe
+        slots: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+
+    (
+        RingProducer {
+            shared: shared.clone(),
+        },
+        RingConsumer { shared },
+    )
+}
+
+struct Shared {
+    slots: Box<[AtomicU32]>,
+    // total samples ever written and read, indices wrap around `slots`
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl Shared {
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: usize) -> &AtomicU32 {
+        &self.slots[index % self.slots.len()]
+    }
+}
+
+pub struct RingProducer {
+    shared: Arc<Shared>,
+}
+
+impl RingProducer {
+    /// Writes as much of `samples` as fits and returns how many were written.
+    pub fn push_slice(&mut self, samples: &[f32]) -> usize {
+        let head = self.shared.head.load(Ordering::Relaxed);
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        let free = self.shared.capacity() - head.wrapping_sub(tail);
+        let count = samples.len().min(free);
+
+        for (i, sample) in samples[..count].iter().enumerate() {
+            self.shared
+                .slot(head.wrapping_add(i))
+                .store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.shared
+            .head
+            .store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Writes a single sample, dropping it if the ring is full.
+    pub fn push(&mut self, sample: f32) -> bool {
+        self.push_slice(&[sample]) == 1
+    }
+}
+
+pub struct RingConsumer {
+    shared: Arc<Shared>,
+}
+
+impl RingConsumer {
+    /// Samples ready to be read.
+    pub fn len(&self) -> usize {
+        let tail = self.shared.tail.load(Ordering::Relaxed);
+        self.shared.head.load(Ordering::Acquire).wrapping_sub(tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills `out` from the front of the ring and returns how many samples were read.
+    pub fn pop_slice(&mut self, out: &mut [f32]) -> usize {
+        let tail = self.shared.tail.load(Ordering::Relaxed);
+        let count = out.len().min(self.len());
+
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            let bits = self
+                .shared
+                .slot(tail.wrapping_add(i))
+                .load(Ordering::Relaxed);
+            *sample = f32::from_bits(bits);
+        }
+        self.shared
+            .tail
+            .store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    pub fn pop(&mut self) -> Option<f32> {
+        let mut sample = [0.0];
+        (self.pop_slice(&mut sample) == 1).then_some(sample[0])
+    }
+
+    /// Drops everything written so far.
+    pub fn clear(&mut self) {
+        let head = self.shared.head.load(Ordering::Acquire);
+        self.shared.tail.store(head, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_order_across_wraparound() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        let mut out = [0.0; 3];
+        for round in 0..5 {
+            let base = round as f32 * 3.0;
+            assert_eq!(producer.push_slice(&[base, base + 1.0, base + 2.0]), 3);
+            assert_eq!(consumer.pop_slice(&mut out), 3);
+            assert_eq!(out, [base, base + 1.0, base + 2.0]);
+        }
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn full_ring_takes_no_more() {
+        let (mut producer, mut consumer) = ring_buffer(3);
+        assert_eq!(producer.push_slice(&[1.0, 2.0, 3.0, 4.0]), 3);
+        assert!(!producer.push(5.0));
+        assert_eq!(consumer.len(), 3);
+
+        assert_eq!(consumer.pop(), Some(1.0));
+        assert!(producer.push(6.0));
+        let mut out = [0.0; 4];
+        assert_eq!(consumer.pop_slice(&mut out), 3);
+        assert_eq!(out[..3], [2.0, 3.0, 6.0]);
+    }
+
+    #[test]
+    fn empty_ring_reads_nothing() {
+        let (_producer, mut consumer) = ring_buffer(4);
+        let mut out = [7.0; 2];
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.pop_slice(&mut out), 0);
+        assert_eq!(out, [7.0; 2]);
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn clear_drops_queued_samples() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        producer.push_slice(&[1.0, 2.0, 3.0]);
+        consumer.clear();
+        assert!(consumer.is_empty());
+
+        // the whole capacity is free again
+        assert_eq!(producer.push_slice(&[4.0, 5.0, 6.0, 7.0]), 4);
+        assert_eq!(consumer.pop(), Some(4.0));
+    }
+}
@@ -1,5 +1,4 @@
 use eframe::{App, Frame, egui};
-use egui::frame;
 use fundsp::{math::midi_hz, shared::Shared};
 use std::sync::{Arc, Mutex};
 
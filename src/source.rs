@@ -0,0 +1,293 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::audio::{DecodedAudio, StreamingDecoder};
+
+pub type SourceError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Anything that can hand out interleaved audio frames: decoded files,
+/// in-memory buffers, live input or generated test signals.
+pub trait AudioSource: Send {
+    fn sample_rate(&self) -> f32;
+
+    fn channels(&self) -> usize;
+
+    /// Reads up to `max_frames` interleaved frames.
+    /// An empty vec means the source is exhausted.
+    fn read(&mut self, max_frames: usize) -> Result<Vec<f32>, SourceError>;
+
+    /// Seeks to `time` and returns the new frame position.
+    fn seek(&mut self, time: Duration) -> Result<usize, SourceError>;
+
+    /// Total length, or `None` for live and unbounded sources.
+    fn duration(&self) -> Option<Duration>;
+
+    /// Current read position in frames.
+    fn position(&self) -> usize;
+
+    /// Short description for the visualizer header.
+    fn name(&self) -> Option<String> {
+        None
+    }
+}
+
+impl AudioSource for StreamingDecoder {
+    fn sample_rate(&self) -> f32 {
+        StreamingDecoder::sample_rate(self)
+    }
+
+    fn channels(&self) -> usize {
+        StreamingDecoder::channels(self)
+    }
+
+    fn read(&mut self, max_frames: usize) -> Result<Vec<f32>, SourceError> {
+        Ok(StreamingDecoder::read(self, max_frames)?)
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<usize, SourceError> {
+        Ok(StreamingDecoder::seek(self, time)?)
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.total_duration()
+    }
+
+    fn position(&self) -> usize {
+        StreamingDecoder::position(self)
+    }
+
+    fn name(&self) -> Option<String> {
+        self.track_info().display_title()
+    }
+}
+
+/// Interleaved samples held entirely in memory.
+pub struct MemorySource {
+    samples: Vec<f32>,
+    sample_rate: f32,
+    channels: usize,
+    position: usize,
+    name: Option<String>,
+}
+
+impl MemorySource {
+    pub fn new(samples: Vec<f32>, sample_rate: f32, channels: usize) -> Self {
+        Self {
+            samples,
+            sample_rate,
+            channels: channels.max(1),
+            position: 0,
+            name: None,
+        }
+    }
+
+    fn total_frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+}
+
+impl From<DecodedAudio> for MemorySource {
+    fn from(decoded: DecodedAudio) -> Self {
+        let mut source = MemorySource::new(decoded.samples, decoded.sample_rate, decoded.channels);
+        source.name = decoded.info.display_title();
+        source
+    }
+}
+
+impl AudioSource for MemorySource {
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, max_frames: usize) -> Result<Vec<f32>, SourceError> {
+        let end = (self.position + max_frames).min(self.total_frames());
+        let chunk = self.samples[self.position * self.channels..end * self.channels].to_vec();
+        self.position = end;
+        Ok(chunk)
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<usize, SourceError> {
+        let target = (time.as_secs_f64() * self.sample_rate as f64) as usize;
+        self.position = target.min(self.total_frames());
+        Ok(self.position)
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(
+            self.total_frames() as f64 / self.sample_rate as f64,
+        ))
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+}
+
+/// Generated test signals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    Sine {
+        frequency: f32,
+    },
+    /// exponential sweep from `start` to `end` Hz over the source duration
+    Sweep {
+        start: f32,
+        end: f32,
+    },
+    /// white noise
+    Noise,
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    // sine[:freq] | sweep[:start:end] | noise
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let params = parts
+            .map(|part| part.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|_| format!("invalid signal parameters in '{}'", s))?;
+
+        match (kind.as_str(), params.as_slice()) {
+            ("sine", []) => Ok(Signal::Sine { frequency: 440.0 }),
+            ("sine", [frequency]) => Ok(Signal::Sine {
+                frequency: *frequency,
+            }),
+            ("sweep", []) => Ok(Signal::Sweep {
+                start: 20.0,
+                end: 20000.0,
+            }),
+            ("sweep", [start, end]) if *start > 0.0 && *end > 0.0 => Ok(Signal::Sweep {
+                start: *start,
+                end: *end,
+            }),
+            ("noise", []) => Ok(Signal::Noise),
+            _ => Err(format!(
+                "unknown signal '{}', expected sine[:freq], sweep[:start:end] or noise",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Signal::Sine { frequency } => write!(f, "sine {} Hz", frequency),
+            Signal::Sweep { start, end } => write!(f, "sweep {}-{} Hz", start, end),
+            Signal::Noise => write!(f, "white noise"),
+        }
+    }
+}
+
+/// Mono test signal of a fixed length.
+pub struct SignalSource {
+    signal: Signal,
+    sample_rate: f32,
+    amplitude: f32,
+    total_frames: usize,
+    position: usize,
+    noise_state: u32,
+}
+
+impl SignalSource {
+    pub fn new(signal: Signal, sample_rate: f32, duration: Duration) -> Self {
+        let mut source = Self {
+            signal,
+            sample_rate,
+            amplitude: 0.5,
+            total_frames: (duration.as_secs_f64() * sample_rate as f64) as usize,
+            position: 0,
+            noise_state: 0,
+        };
+        source.reseed();
+        source
+    }
+
+    // noise depends only on position so seeking is repeatable
+    fn reseed(&mut self) {
+        self.noise_state = (self.position as u32).wrapping_mul(0x9E37_79B9) | 1;
+    }
+
+    fn sample_at(&mut self, frame: usize) -> f32 {
+        let t = frame as f64 / self.sample_rate as f64;
+        let value = match self.signal {
+            Signal::Sine { frequency } => (2.0 * PI * frequency as f64 * t).sin(),
+            Signal::Sweep { start, end } => {
+                let length = self.total_frames.max(1) as f64 / self.sample_rate as f64;
+                let (start, end) = (start as f64, end as f64);
+                if (end - start).abs() < f64::EPSILON {
+                    (2.0 * PI * start * t).sin()
+                } else {
+                    // integral of the instantaneous frequency start * (end/start)^(t/length)
+                    let rate = (end / start).ln();
+                    let phase =
+                        2.0 * PI * start * length / rate * ((t / length * rate).exp() - 1.0);
+                    phase.sin()
+                }
+            }
+            Signal::Noise => {
+                // xorshift32
+                let mut x = self.noise_state;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.noise_state = x;
+                x as f64 / u32::MAX as f64 * 2.0 - 1.0
+            }
+        };
+        value as f32 * self.amplitude
+    }
+}
+
+impl AudioSource for SignalSource {
+    fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, max_frames: usize) -> Result<Vec<f32>, SourceError> {
+        let end = (self.position + max_frames).min(self.total_frames);
+        let chunk = (self.position..end)
+            .map(|frame| self.sample_at(frame))
+            .collect();
+        self.position = end;
+        Ok(chunk)
+    }
+
+    fn seek(&mut self, time: Duration) -> Result<usize, SourceError> {
+        let target = (time.as_secs_f64() * self.sample_rate as f64) as usize;
+        self.position = target.min(self.total_frames);
+        self.reseed();
+        Ok(self.position)
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(
+            self.total_frames as f64 / self.sample_rate as f64,
+        ))
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn name(&self) -> Option<String> {
+        Some(self.signal.to_string())
+    }
+}
@@ -1,53 +1,78 @@
 use std::{
     sync::{Arc, Mutex, mpsc},
     thread,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
+use crate::clock::PlaybackClock;
+use crate::source::AudioSource;
+
+/// Timestamp and interleaved samples of one streamed chunk.
+pub type AnalysisChunk = (Duration, Vec<f32>);
+
+/// First frame and interleaved samples of one chunk sent for playback,
+/// empty at the end of the source.
+pub type AudioChunk = (usize, Vec<f32>);
+
 pub struct AudioStreamer {
-    samples: Vec<f32>,
+    source: Arc<Mutex<Box<dyn AudioSource>>>,
     sample_rate: f32,
+    channels: usize,
+    total_frames: Option<usize>,
+    // read position in frames, ahead of playback by whatever is buffered
     current_position: Arc<Mutex<usize>>,
+    // what is actually audible, driven by the output callback
+    clock: PlaybackClock,
 
-    audio_sender: mpsc::Sender<Vec<f32>>,
-    analysis_sender: mpsc::Sender<(Duration, Vec<f32>)>,
+    audio_sender: mpsc::Sender<AudioChunk>,
+    analysis_sender: mpsc::Sender<AnalysisChunk>,
 
+    // chunk size in frames
     chunk_size: usize,
-    update_interval: Duration,
+    // how far reading may run ahead of playback
+    max_buffered: Duration,
 
     // pause control
     is_paused: Arc<Mutex<bool>>,
+    // set once the last chunk has been sent
+    reached_end: Arc<Mutex<bool>>,
 }
 
 impl AudioStreamer {
     pub fn new(
-        samples: Vec<f32>,
-        sample_rate: f32,
+        source: Box<dyn AudioSource>,
         chunk_duration_ms: u64,
     ) -> (
         Self,
-        mpsc::Receiver<Vec<f32>>,
-        mpsc::Receiver<(Duration, Vec<f32>)>,
+        mpsc::Receiver<AudioChunk>,
+        mpsc::Receiver<AnalysisChunk>,
     ) {
+        let sample_rate = source.sample_rate();
+        let channels = source.channels();
+        let total_frames = source
+            .duration()
+            .map(|duration| (duration.as_secs_f64() * sample_rate as f64).round() as usize);
         let chunk_size = ((sample_rate * chunk_duration_ms as f32) / 1000.0) as usize;
-        // streaming interval should be slightly faster
-        // so that buffer fills up faster than drains
-        let streaming_interval = (chunk_duration_ms as f64 / 1.26) as u64;
-        let update_interval = Duration::from_millis(streaming_interval);
+        // a few chunks ahead so analysis results are ready before they are heard
+        let max_buffered = Duration::from_millis(chunk_duration_ms * 3);
 
         let (audio_tx, audio_rx) = mpsc::channel();
         let (analysis_tx, analysis_rx) = mpsc::channel();
 
         (
             Self {
-                samples,
+                source: Arc::new(Mutex::new(source)),
                 sample_rate,
+                channels,
+                total_frames,
                 current_position: Arc::new(Mutex::new(0)),
+                clock: PlaybackClock::new(sample_rate),
                 audio_sender: audio_tx,
                 analysis_sender: analysis_tx,
                 chunk_size,
-                update_interval,
+                max_buffered,
                 is_paused: Arc::new(Mutex::new(false)),
+                reached_end: Arc::new(Mutex::new(false)),
             },
             audio_rx,
             analysis_rx,
@@ -55,57 +80,62 @@ impl AudioStreamer {
     }
 
     pub fn start_streaming(&self) {
-        let samples = self.samples.clone();
+        let source = self.source.clone();
         let sample_rate = self.sample_rate;
         let current_position = self.current_position.clone();
         let audio_sender = self.audio_sender.clone();
         let analysis_sender = self.analysis_sender.clone();
         let chunk_size = self.chunk_size;
-        let update_interval = self.update_interval;
+        let total_frames = self.total_frames;
+        let clock = self.clock.clone();
+        let max_buffered = self.max_buffered;
         let is_paused = self.is_paused.clone();
+        let reached_end = self.reached_end.clone();
 
         thread::spawn(move || {
-            let mut last_update = Instant::now();
-
             loop {
                 // check if paused
                 let paused = *is_paused.lock().unwrap();
                 if paused {
                     thread::sleep(Duration::from_millis(50));
-                    last_update = Instant::now();
                     continue;
                 }
 
-                // wait for next update
-                let elapsed = last_update.elapsed();
-                if elapsed < update_interval {
-                    thread::sleep(update_interval - elapsed);
+                // wait while enough audio is queued ahead of playback
+                let read_time =
+                    Duration::from_secs_f32(*current_position.lock().unwrap() as f32 / sample_rate);
+                if read_time.saturating_sub(clock.now()) >= max_buffered {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
                 }
-                last_update = Instant::now();
 
-                // get curr position and read next chunk
-                let (chunk, timestamp, is_end) = {
-                    let mut pos = current_position.lock().unwrap();
-                    let start_pos = *pos;
-
-                    if start_pos >= samples.len() {
-                        break; // end of file
+                // read next chunk from curr position
+                let (chunk, start_pos, timestamp, is_end) = {
+                    let mut source = source.lock().unwrap();
+                    let start_pos = source.position();
+                    let chunk = match source.read(chunk_size) {
+                        Ok(chunk) => chunk,
+                        Err(err) => {
+                            eprintln!("Stopped streaming: {}", err);
+                            break;
+                        }
+                    };
+
+                    if chunk.is_empty() {
+                        break; // end of source
                     }
 
-                    let end_pos = (start_pos + chunk_size).min(samples.len());
-                    let chunk = samples[start_pos..end_pos].to_vec();
-
-                    // calc timestamp based on sample pos
+                    // calc timestamp based on frame pos
                     let timestamp = Duration::from_secs_f32(start_pos as f32 / sample_rate);
 
-                    *pos = end_pos;
-                    let is_end = end_pos >= samples.len();
+                    *current_position.lock().unwrap() = source.position();
+                    let is_end = total_frames.is_some_and(|total| source.position() >= total);
 
-                    (chunk, timestamp, is_end)
+                    (chunk, start_pos, timestamp, is_end)
                 };
 
                 // send data to audio buffer
-                if audio_sender.send(chunk.clone()).is_err() {
+                if audio_sender.send((start_pos, chunk.clone())).is_err() {
                     println!("Audio output buffer full, skipping chunk");
                 }
 
@@ -115,43 +145,77 @@ impl AudioStreamer {
                 }
 
                 if is_end {
-                    println!("Reached end of audio file");
+                    println!("Reached end of audio source");
                     break;
                 }
             }
+
+            // an empty chunk tells the output to play out what it holds back
+            let end_pos = source.lock().unwrap().position();
+            let _ = audio_sender.send((end_pos, Vec::new()));
+            *reached_end.lock().unwrap() = true;
         });
     }
 
+    /// Clock shared with the output, which advances it as frames are played.
+    pub fn clock(&self) -> PlaybackClock {
+        self.clock.clone()
+    }
+
+    /// Time of the frame that is audible right now.
     pub fn get_current_time(&self) -> Duration {
-        let position = *self.current_position.lock().unwrap();
-        Duration::from_secs_f32(position as f32 / self.sample_rate)
+        self.clock.now()
     }
 
+    /// Frame that is audible right now.
     pub fn get_current_position(&self) -> usize {
-        *self.current_position.lock().unwrap()
+        (self.clock.now().as_secs_f64() * self.sample_rate as f64) as usize
     }
 
-    pub fn seek_to_time(&self, time: Duration) {
-        let target_position = (time.as_secs_f32() * self.sample_rate) as usize;
-        let mut position = self.current_position.lock().unwrap();
-        *position = target_position.min(self.samples.len());
+    /// Moves reading and the clock to `time` and returns the frame the
+    /// source landed on, where it already was if seeking failed.
+    pub fn seek_to_time(&self, time: Duration) -> usize {
+        let mut source = self.source.lock().unwrap();
+        match source.seek(time) {
+            Ok(new_position) => {
+                *self.current_position.lock().unwrap() = new_position;
+                // playback continues from wherever the source landed
+                self.clock.reset(Duration::from_secs_f64(
+                    new_position as f64 / self.sample_rate as f64,
+                ));
+                new_position
+            }
+            Err(err) => {
+                eprintln!("Failed to seek: {}", err);
+                source.position()
+            }
+        }
     }
 
-    pub fn seek_to_position(&self, sample_position: usize) {
-        let mut position = self.current_position.lock().unwrap();
-        *position = sample_position.min(self.samples.len());
+    /// Total duration of the source, or `Duration::MAX` if the container
+    /// does not report a length.
+    pub fn get_total_duration(&self) -> Duration {
+        match self.total_frames {
+            Some(total_frames) => Duration::from_secs_f32(total_frames as f32 / self.sample_rate),
+            None => Duration::MAX,
+        }
     }
 
-    pub fn get_total_duration(&self) -> Duration {
-        Duration::from_secs_f32(self.samples.len() as f32 / self.sample_rate)
+    pub fn channels(&self) -> usize {
+        self.channels
     }
 
+    /// True once the last frame has been played.
     pub fn is_finished(&self) -> bool {
-        let position = *self.current_position.lock().unwrap();
-        position >= self.samples.len()
+        if !*self.reached_end.lock().unwrap() {
+            return false;
+        }
+        // allow for rounding between device and source frames
+        self.get_current_position() + 1 >= *self.current_position.lock().unwrap()
     }
 
-    pub fn skip_forward(&self, seconds: f32) -> Duration {
+    /// Seeks `seconds` ahead and returns the frame the source landed on.
+    pub fn skip_forward(&self, seconds: f32) -> usize {
         let current_time = self.get_current_time();
         let new_time = current_time + Duration::from_secs_f32(seconds);
         let total_duration = self.get_total_duration();
@@ -162,11 +226,11 @@ impl AudioStreamer {
             new_time
         };
 
-        self.seek_to_time(clamped_time);
-        clamped_time
+        self.seek_to_time(clamped_time)
     }
 
-    pub fn skip_backward(&self, seconds: f32) -> Duration {
+    /// Seeks `seconds` back and returns the frame the source landed on.
+    pub fn skip_backward(&self, seconds: f32) -> usize {
         let current_time = self.get_current_time();
         let seconds_duration = Duration::from_secs_f32(seconds);
 
@@ -176,18 +240,7 @@ impl AudioStreamer {
             Duration::ZERO
         };
 
-        self.seek_to_time(new_time);
-        new_time
-    }
-
-    pub fn pause(&self) {
-        let mut paused = self.is_paused.lock().unwrap();
-        *paused = true;
-    }
-
-    pub fn resume(&self) {
-        let mut paused = self.is_paused.lock().unwrap();
-        *paused = false;
+        self.seek_to_time(new_time)
     }
 
     pub fn toggle(&self) {
@@ -1,38 +1,42 @@
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, FromSample, SampleFormat, SizedSample, StreamConfig};
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-use fundsp::hacker::{
-    hammond_hz, multipass, reverb_stereo, shared, sine, sine_hz, soft_saw_hz, square_hz, var,
-    var_fn,
-};
+use fundsp::hacker::{shared, sine, var, var_fn};
 use fundsp::math::midi_hz;
 use fundsp::prelude::AudioUnit;
 use fundsp::shared::Shared;
-use std::any::Any;
-use std::io::{self, Write};
-use std::sync::{Arc, Mutex, mpsc};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use crate::backend::{OutputBackend, OutputStream};
 use crate::soundboard::SynthApp;
 
-#[derive(Debug, Clone, Copy)]
-enum InputEvent {
-    KeyDown(char),
-    KeyUp(char),
-    Quit,
-}
-
 /// Starts the audio synthesis, playing a sine wave (A4, 440Hz) for the specified
 /// duration in seconds. This function is blocking for the duration of playback.
-pub fn run_synthesizer(should_quit: Arc<Mutex<bool>>) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run_synthesizer(
+    should_quit: Arc<Mutex<bool>>,
+    backend: &dyn OutputBackend,
+    demo: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if backend.is_headless() && !demo {
+        return Err("a headless output has no keyboard, pass --demo to play a melody".into());
+    }
     let gate = shared(0.0);
     let frequency = shared(midi_hz(60.0));
     let audio_graph = create_gated_sine(gate.clone(), frequency.clone());
     let should_quit_clone = should_quit.clone();
     // start output stream to play audio graph
-    run_output(audio_graph);
+    let _stream = run_output(audio_graph, backend)?;
+
+    if demo {
+        // play the first notes of the keyboard instead of opening it
+        for midi_note in [60.0, 62.0, 64.0, 65.0] {
+            frequency.set_value(midi_hz(midi_note));
+            gate.set_value(1.0);
+            std::thread::sleep(Duration::from_millis(400));
+            gate.set_value(0.0);
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        *should_quit.lock().unwrap() = true;
+        return Ok(());
+    }
 
     let options = eframe::NativeOptions::default();
 
@@ -56,73 +60,34 @@ pub fn run_synthesizer(should_quit: Arc<Mutex<bool>>) -> Result<(), Box<dyn std:
 // Core Audio Functions
 // ------------------------------------------------------------------
 
-/// This function determines the sample format, which depends on your system,
-/// then starts the synth, passing along the audio graph to generate the sound.
+/// Starts the output, passing along the audio graph to generate the sound.
+/// Audio plays until the returned stream is dropped.
 // UPDATED: Changed AudioUnit64 to AudioUnit
-fn run_output(audio_graph: Box<dyn AudioUnit>) {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("failed to find a default output device");
-    let config = device.default_output_config().unwrap();
-
-    // Match the system's required sample format and start the synth stream
-    match config.sample_format() {
-        SampleFormat::F32 => run_synth::<f32>(audio_graph, device, config.into()),
-        SampleFormat::I16 => run_synth::<i16>(audio_graph, device, config.into()),
-        SampleFormat::U16 => run_synth::<u16>(audio_graph, device, config.into()),
-        _ => panic!("Unsupported format"),
-    }
-}
-
-/// Starts a thread that will play the audio using the provided audio graph.
-fn run_synth<T: SizedSample + FromSample<f64>>(
+fn run_output(
     mut audio_graph: Box<dyn AudioUnit>,
-    device: Device,
-    config: StreamConfig,
-) {
-    // Spawning a thread to handle audio playback in the background
-    std::thread::spawn(move || {
-        let sample_rate = config.sample_rate.0 as f64;
-        audio_graph.set_sample_rate(sample_rate);
-
-        // Closure to get the next stereo audio sample from the graph
-        // Note: AudioUnit::get_stereo() returns (f64, f64), which matches this setup.
-        let mut next_value = move || audio_graph.get_stereo();
-
-        let channels = config.channels as usize;
-        let err_fn = |err| eprintln!("an error occurred on stream: {err}");
-
-        let stream = device
-            .build_output_stream(
-                &config,
-                move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                    write_data(data, channels, &mut next_value)
-                },
-                err_fn,
-                None,
-            )
-            .unwrap();
-
-        stream.play().unwrap();
-
-        // Keep the thread alive so the audio stream continues
-        loop {
-            std::thread::sleep(Duration::from_millis(1));
-        }
-    });
+    backend: &dyn OutputBackend,
+) -> Result<OutputStream, Box<dyn std::error::Error>> {
+    let config = backend.default_config()?;
+    audio_graph.set_sample_rate(config.sample_rate as f64);
+
+    // Closure to get the next stereo audio sample from the graph
+    // Note: AudioUnit::get_stereo() returns (f32, f32), which matches this setup.
+    let mut next_value = move || audio_graph.get_stereo();
+
+    let channels = config.channels;
+    backend.start(
+        config,
+        Box::new(move |data: &mut [f32], _| {
+            write_data(data, channels, &mut next_value);
+            data.len()
+        }),
+    )
 }
 
 /// Generates audio samples and writes them to the output buffer.
-fn write_data<T: SizedSample + FromSample<f64>>(
-    output: &mut [T],
-    channels: usize,
-    next_sample: &mut dyn FnMut() -> (f32, f32),
-) {
+fn write_data(output: &mut [f32], channels: usize, next_sample: &mut dyn FnMut() -> (f32, f32)) {
     for frame in output.chunks_mut(channels) {
-        let sample = next_sample();
-        let left: T = T::from_sample(sample.0 as f64);
-        let right: T = T::from_sample(sample.1 as f64);
+        let (left, right) = next_sample();
 
         // Write the left/right samples to the channels
         for (channel, sample) in frame.iter_mut().enumerate() {
@@ -135,30 +100,6 @@ fn write_data<T: SizedSample + FromSample<f64>>(
 // Audio Graph Creation Functions
 // ------------------------------------------------------------------
 
-/// Simple sine wave at 440 Hz (standard tuning for A4)
-// UPDATED: Changed AudioUnit64 to AudioUnit
-fn create_sine_440() -> Box<dyn AudioUnit> {
-    let synth = sine_hz(440.0);
-    Box::new(synth)
-}
-
-/// C major chord created by summing sine waves.
-#[allow(dead_code)]
-fn create_c_major() -> Box<dyn AudioUnit> {
-    let synth = soft_saw_hz(261.6) + soft_saw_hz(329.628) + soft_saw_hz(391.995);
-    Box::new(synth)
-}
-
-/// Simple FM synthesiser taken from the FunDSP docs
-#[allow(dead_code)]
-fn create_simple_fm() -> Box<dyn AudioUnit> {
-    // Frequency (f) and Modulation index (m)
-    let f = 440.0;
-    let m = 5.0;
-    let synth = (sine_hz(f) * f * m + f) >> sine();
-    Box::new(synth)
-}
-
 fn create_gated_sine(gate: Shared, frequency: Shared) -> Box<dyn AudioUnit> {
     let freq_var = var_fn(&frequency, |f| f);
 
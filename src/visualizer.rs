@@ -6,20 +6,78 @@ use std::{
     time::Duration,
 };
 
+use crate::aux::AnalysisResult;
+use crate::chroma::{Chroma, chroma_bars};
+use crate::key::Key;
+use crate::notes::Chord;
+
 #[derive(Debug, Clone)]
 pub struct VisualizerData {
     pub current_time: Duration,
     pub amplitude_samples: Vec<f32>,
     pub note_history: VecDeque<(Duration, String)>,
     pub current_note: Option<String>,
+    // chord of all notes sounding together
+    pub chord: Option<Chord>,
+    // pitch class profile of the latest analysis
+    pub chroma: Option<Chroma>,
+    // rolling key estimate
+    pub key: Option<Key>,
+    pub tempo: Option<f32>,
+    // recent and upcoming beats, flashed as playback passes them
+    pub beats: VecDeque<Duration>,
     pub total_duration: Duration,
+    // "artist - title" of the playing track
+    pub track_title: Option<String>,
+}
+
+impl VisualizerData {
+    /// Adds a note to the history when it differs from the last one, so a
+    /// held note shows once however many windows it lasts.
+    pub fn add_note(&mut self, timestamp: Duration, note: String) {
+        if self
+            .note_history
+            .back()
+            .is_some_and(|(_, last)| *last == note)
+        {
+            return;
+        }
+        self.note_history.push_back((timestamp, note));
+        if self.note_history.len() > 20 {
+            self.note_history.pop_front();
+        }
+    }
+
+    /// Shows an analysis result, as it becomes audible or is captured.
+    pub fn apply(&mut self, result: &AnalysisResult) {
+        let note = result.to_string();
+        self.current_note = Some(note.clone());
+        self.chord = result.chord;
+        self.chroma = Some(result.chroma);
+        self.key = result.key;
+        self.tempo = result.tempo;
+        self.add_beats(&result.beats);
+        self.add_note(result.timestamp, note);
+    }
+
+    /// Queues beats to flash, keeping only the latest few.
+    pub fn add_beats(&mut self, beats: &[Duration]) {
+        self.beats.extend(beats);
+        while self.beats.len() > 16 {
+            self.beats.pop_front();
+        }
+    }
 }
 
+// how long the beat indicator stays lit
+const BEAT_FLASH: Duration = Duration::from_millis(120);
+
 pub struct TerminalVisualizer {
     shared_data: Arc<Mutex<VisualizerData>>,
     refresh_rate: Duration,
     waveform_width: usize,
     history_lines: usize,
+    controls: &'static str,
 }
 
 impl TerminalVisualizer {
@@ -33,7 +91,13 @@ impl TerminalVisualizer {
             amplitude_samples: Vec::new(),
             note_history: VecDeque::new(),
             current_note: None,
+            chord: None,
+            chroma: None,
+            key: None,
+            tempo: None,
+            beats: VecDeque::new(),
             total_duration: Duration::ZERO,
+            track_title: None,
         }));
 
         let visualizer = Self {
@@ -41,16 +105,24 @@ impl TerminalVisualizer {
             refresh_rate: Duration::from_millis(refresh_rate_ms),
             waveform_width,
             history_lines,
+            controls: "Controls: [q]uit | [j][l] seek ±5s | [k] start/stop | [p] pitch bands/yin",
         };
 
         (visualizer, shared_data)
     }
 
+    /// Replaces the controls hint printed below the content area.
+    pub fn with_controls(mut self, controls: &'static str) -> Self {
+        self.controls = controls;
+        self
+    }
+
     pub fn start_rendering(&self) -> std::thread::JoinHandle<()> {
         let shared_data = self.shared_data.clone();
         let refresh_rate = self.refresh_rate;
         let waveform_width = self.waveform_width;
         let history_lines = self.history_lines;
+        let controls = self.controls;
 
         thread::spawn(move || {
             // push existing content up by printing 30 blank lines
@@ -60,7 +132,7 @@ impl TerminalVisualizer {
             // initialize fixed lines once
             print!("\x1B[?25l"); // hide cursor
             print!("\x1B[31;0H"); // line 29
-            print!("Controls: [q]uit | [j][l] seek ±5s | [k] start/stop");
+            print!("{}", controls);
             print!("\x1B[32;0H"); // line 30
             print!("Command (then press Enter): ");
 
@@ -88,14 +160,40 @@ impl TerminalVisualizer {
         print!("\x1B[H");
 
         // Render all dynamic content
-        println!(
-            "🎵 Time: {:?} / {:?}",
-            data.current_time, data.total_duration
-        );
+        // live input has no total duration
+        let time = if data.total_duration.is_zero() {
+            format!("{:?}", data.current_time)
+        } else {
+            format!("{:?} / {:?}", data.current_time, data.total_duration)
+        };
+        let key = match &data.key {
+            Some(key) => format!(" | Key: {} ({:.0}%)", key, key.confidence * 100.0),
+            None => String::new(),
+        };
+        let tempo = match data.tempo {
+            Some(bpm) => {
+                let on_beat = data.beats.iter().any(|&beat| {
+                    data.current_time >= beat && data.current_time - beat < BEAT_FLASH
+                });
+                format!(" | {:.0} BPM {}", bpm, if on_beat { "●" } else { "○" })
+            }
+            None => String::new(),
+        };
+        match &data.track_title {
+            Some(title) => println!("🎵 {} | {}{}{}", title, time, key, tempo),
+            None => println!("🎵 Time: {}{}{}", time, key, tempo),
+        }
 
         let current_note = data.current_note.as_deref().unwrap_or("♪ Analyzing...");
-        println!("🎼 Current: {}", current_note);
-        println!();
+        match &data.chord {
+            Some(chord) => println!("🎼 Current: {}  ≈ {}", current_note, chord),
+            None => println!("🎼 Current: {}", current_note),
+        }
+        // takes the blank line before the waveform so the layout keeps its height
+        match &data.chroma {
+            Some(chroma) => println!("🎹 Chroma: {}", chroma_bars(chroma)),
+            None => println!(),
+        }
 
         println!("Waveform:");
         Self::render_waveform(&data.amplitude_samples, waveform_width);
@@ -122,34 +220,39 @@ impl TerminalVisualizer {
 
         let mut grid = vec![vec![' '; width]; max_height];
 
-        for i in 0..width {
-            let sample_idx = (i * step).min(samples.len() - 1);
-            let amplitude = samples[sample_idx].abs();
+        // column heights in eighths of a row
+        let heights: Vec<usize> = (0..width)
+            .map(|i| {
+                let sample_idx = (i * step).min(samples.len() - 1);
+                let amplitude = samples[sample_idx].abs();
 
-            // Scale to max possible height with sub-pixel precision
-            let total_height = (amplitude * (max_height * 8) as f32) as usize;
+                // Scale to max possible height with sub-pixel precision
+                (amplitude * (max_height * 8) as f32) as usize
+            })
+            .collect();
 
-            // Fill full blocks
-            let full_blocks = total_height / 8;
-            for row in 0..full_blocks.min(max_height) {
-                let grid_row = max_height - 1 - row;
-                grid[grid_row][i] = '█';
-            }
+        for (grid_row, row) in grid.iter_mut().enumerate() {
+            let level = max_height - 1 - grid_row;
+            for (cell, height) in row.iter_mut().zip(&heights) {
+                let full_blocks = height / 8;
+                let remainder = height % 8;
 
-            // Fill partial block at top
-            let remainder = total_height % 8;
-            if full_blocks < max_height && remainder > 0 {
-                let grid_row = max_height - 1 - full_blocks;
-                grid[grid_row][i] = match remainder {
-                    1 => '▁',
-                    2 => '▂',
-                    3 => '▃',
-                    4 => '▄',
-                    5 => '▅',
-                    6 => '▆',
-                    7 => '▇',
-                    _ => '█',
-                };
+                if full_blocks > level {
+                    // Fill full blocks
+                    *cell = '█';
+                } else if full_blocks == level && remainder > 0 {
+                    // Fill partial block at top
+                    *cell = match remainder {
+                        1 => '▁',
+                        2 => '▂',
+                        3 => '▃',
+                        4 => '▄',
+                        5 => '▅',
+                        6 => '▆',
+                        7 => '▇',
+                        _ => '█',
+                    };
+                }
             }
         }
 
@@ -170,10 +273,4 @@ impl TerminalVisualizer {
             println!("{:>8.1}s | {}", timestamp.as_secs_f32(), note);
         }
     }
-
-    pub fn cleanup(&self) {
-        // Show cursor and clear screen
-        print!("\x1B[?25h\x1B[2J\x1B[H");
-        io::stdout().flush().unwrap();
-    }
 }
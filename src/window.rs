@@ -1,36 +1,374 @@
 use std::f32::consts::PI;
+use std::fmt;
+use std::iter::StepBy;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
 
-fn hann_window(window_size: usize) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
-    let mut window_coefficients: Vec<f32> = vec![0.0; window_size];
+// beta and sigma used when `--window kaiser` or `gaussian` gives none
+const DEFAULT_KAISER_BETA: f32 = 8.6;
+const DEFAULT_GAUSSIAN_SIGMA: f32 = 0.4;
 
-    for (i, coeff) in window_coefficients.iter_mut().enumerate() {
-        let position = i as f32 / (window_size - 1) as f32;
-        *coeff = 0.5 * (1.0 - (2.0 * PI * position).cos());
+// computed windows, shared by every caller. only a few configs are in use
+// at once, so the oldest window is dropped past this many
+const MAX_CACHED_WINDOWS: usize = 8;
+type WindowCache = Mutex<Vec<((WindowKind, usize), Arc<[f32]>)>>;
+static CACHE: LazyLock<WindowCache> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Taper applied to each window before the fft. Narrow main lobes resolve
+/// close notes, low sidelobes keep quiet notes from drowning in loud ones.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WindowKind {
+    #[default]
+    Hann,
+    Hamming,
+    Blackman,
+    /// four term, sidelobes below -92 dB
+    BlackmanHarris,
+    /// wide main lobe but accurate peak amplitudes
+    FlatTop,
+    /// trades main lobe width for sidelobe level with beta
+    Kaiser(f32),
+    /// standard deviation relative to half the window
+    Gaussian(f32),
+}
+
+impl WindowKind {
+    /// Window of `window_size` coefficients, scaled to a mean of 1 so a
+    /// sine has the same peak magnitude whichever window is used.
+    pub fn coefficients(&self, window_size: usize) -> Arc<[f32]> {
+        let key = (*self, window_size);
+        let mut cache = CACHE.lock().unwrap();
+        if let Some((_, window)) = cache.iter().find(|(cached, _)| *cached == key) {
+            return window.clone();
+        }
+        if cache.len() >= MAX_CACHED_WINDOWS {
+            cache.remove(0);
+        }
+        let window: Arc<[f32]> = self.compute(window_size).into();
+        cache.push((key, window.clone()));
+        window
+    }
+
+    // the periodic form, one period of the taper over the window with the
+    // last zero left out, so the fft sees no repeated sample and windows
+    // overlapped by the hop sum evenly
+    fn compute(&self, window_size: usize) -> Vec<f32> {
+        if window_size < 2 {
+            return vec![1.0; window_size];
+        }
+        let period = window_size as f32;
+        let mut coefficients: Vec<f32> = (0..window_size)
+            .map(|i| {
+                let position = i as f32 / period;
+                match *self {
+                    WindowKind::Hann => cosine_sum(&[0.5, 0.5], position),
+                    WindowKind::Hamming => cosine_sum(&[0.54, 0.46], position),
+                    WindowKind::Blackman => cosine_sum(&[0.42, 0.5, 0.08], position),
+                    WindowKind::BlackmanHarris => {
+                        cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168], position)
+                    }
+                    WindowKind::FlatTop => cosine_sum(
+                        &[
+                            0.215_578_95,
+                            0.416_631_6,
+                            0.277_263_15,
+                            0.083_578_95,
+                            0.006_947_368,
+                        ],
+                        position,
+                    ),
+                    WindowKind::Kaiser(beta) => {
+                        let distance = 2.0 * position - 1.0;
+                        bessel_i0(beta * (1.0 - distance * distance).max(0.0).sqrt())
+                            / bessel_i0(beta)
+                    }
+                    WindowKind::Gaussian(sigma) => {
+                        let distance = (2.0 * position - 1.0) / sigma;
+                        (-0.5 * distance * distance).exp()
+                    }
+                }
+            })
+            .collect();
+
+        // coherent gain normalization
+        let mean = coefficients.iter().sum::<f32>() / window_size as f32;
+        if mean > f32::EPSILON {
+            coefficients.iter_mut().for_each(|coeff| *coeff /= mean);
+        }
+        coefficients
+    }
+}
+
+impl FromStr for WindowKind {
+    type Err = String;
+
+    // `kaiser` and `gaussian` take an optional parameter, e.g. `kaiser:6`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let (name, parameter) = match lower.split_once(':') {
+            Some((name, parameter)) => {
+                let value = parameter
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|value| value.is_finite() && *value > 0.0)
+                    .ok_or_else(|| format!("invalid window parameter '{}'", parameter))?;
+                (name, Some(value))
+            }
+            None => (lower.as_str(), None),
+        };
+        let kind = match name {
+            "hann" | "hanning" => WindowKind::Hann,
+            "hamming" => WindowKind::Hamming,
+            "blackman" => WindowKind::Blackman,
+            "blackman-harris" | "blackmanharris" => WindowKind::BlackmanHarris,
+            "flattop" | "flat-top" => WindowKind::FlatTop,
+            "kaiser" => WindowKind::Kaiser(parameter.unwrap_or(DEFAULT_KAISER_BETA)),
+            "gaussian" => WindowKind::Gaussian(parameter.unwrap_or(DEFAULT_GAUSSIAN_SIGMA)),
+            _ => {
+                return Err(format!(
+                    "unknown window '{}', expected hann, hamming, blackman, blackman-harris, flattop, kaiser[:beta] or gaussian[:sigma]",
+                    s
+                ));
+            }
+        };
+        if parameter.is_some() && !matches!(kind, WindowKind::Kaiser(_) | WindowKind::Gaussian(_)) {
+            return Err(format!("window '{}' takes no parameter", name));
+        }
+        Ok(kind)
+    }
+}
+
+impl fmt::Display for WindowKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowKind::Hann => write!(f, "hann"),
+            WindowKind::Hamming => write!(f, "hamming"),
+            WindowKind::Blackman => write!(f, "blackman"),
+            WindowKind::BlackmanHarris => write!(f, "blackman-harris"),
+            WindowKind::FlatTop => write!(f, "flattop"),
+            WindowKind::Kaiser(beta) => write!(f, "kaiser:{}", beta),
+            WindowKind::Gaussian(sigma) => write!(f, "gaussian:{}", sigma),
+        }
+    }
+}
+
+// a0 - a1 cos(2πx) + a2 cos(4πx) - ...
+fn cosine_sum(terms: &[f32], position: f32) -> f32 {
+    terms
+        .iter()
+        .enumerate()
+        .map(|(k, term)| {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            sign * term * (2.0 * PI * k as f32 * position).cos()
+        })
+        .sum()
+}
+
+// zeroth order modified bessel function of the first kind, by its series
+fn bessel_i0(x: f32) -> f32 {
+    let quarter_square = x * x / 4.0;
+    let (mut sum, mut term) = (1.0, 1.0);
+    for k in 1..50 {
+        term *= quarter_square / (k * k) as f32;
+        sum += term;
+        if term < sum * 1e-8 {
+            break;
+        }
+    }
+    sum
+}
+
+/// How a signal is cut into windows for the fft.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StftConfig {
+    /// samples in each window
+    pub window_size: usize,
+    /// distance in samples between the starts of consecutive windows
+    pub hop_size: usize,
+    /// length of the transform, windows are zero-padded up to it for finer bins
+    pub fft_size: usize,
+    pub window: WindowKind,
+    /// refine peak frequencies from the phase change between windows
+    pub phase_refinement: bool,
+}
+
+impl StftConfig {
+    /// Windows of `window_size` samples overlapping by three quarters,
+    /// without zero-padding.
+    pub const fn new(window_size: usize, window: WindowKind) -> Self {
+        Self {
+            window_size,
+            hop_size: window_size / 4,
+            fft_size: window_size,
+            window,
+            phase_refinement: false,
+        }
+    }
+
+    pub fn with_hop_size(mut self, hop_size: usize) -> Self {
+        self.hop_size = hop_size;
+        self
+    }
+
+    pub fn with_fft_size(mut self, fft_size: usize) -> Self {
+        self.fft_size = fft_size;
+        self
+    }
+
+    pub fn with_phase_refinement(mut self, phase_refinement: bool) -> Self {
+        self.phase_refinement = phase_refinement;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_size < 4 {
+            return Err(format!("window size {} too small", self.window_size));
+        }
+        if self.hop_size == 0 || self.hop_size > self.window_size {
+            return Err(format!(
+                "hop size {} must be between 1 and the window size {}",
+                self.hop_size, self.window_size
+            ));
+        }
+        if self.fft_size < self.window_size {
+            return Err(format!(
+                "fft size {} smaller than the window size {}",
+                self.fft_size, self.window_size
+            ));
+        }
+        // past half the fft size the phase of a bin can move by more than a
+        // full turn between windows, so its change is ambiguous
+        if self.phase_refinement && self.hop_size > self.fft_size / 2 {
+            return Err(format!(
+                "hop size {} over half the fft size {} is too coarse for phase refinement",
+                self.hop_size, self.fft_size
+            ));
+        }
+        Ok(())
     }
 
-    Ok(window_coefficients)
+    /// Start of every full window in `len` samples.
+    pub fn window_starts(&self, len: usize) -> StepBy<Range<usize>> {
+        // nothing to window in input shorter than one window
+        let end = len.checked_sub(self.window_size).map_or(0, |last| last + 1);
+        (0..end).step_by(self.hop_size)
+    }
+
+    /// Windows per second of signal.
+    pub fn frame_rate(&self, sample_rate: f32) -> f32 {
+        sample_rate / self.hop_size as f32
+    }
+}
+
+impl Default for StftConfig {
+    fn default() -> Self {
+        Self::new(2048, WindowKind::Hann)
+    }
 }
 
-pub fn window_audio_samples(
-    samples: &[f32],
-    windowed_samples: &mut Vec<Vec<f32>>,
-    window_size: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let hop_size = window_size / 4;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [WindowKind; 7] = [
+        WindowKind::Hann,
+        WindowKind::Hamming,
+        WindowKind::Blackman,
+        WindowKind::BlackmanHarris,
+        WindowKind::FlatTop,
+        WindowKind::Kaiser(6.0),
+        WindowKind::Gaussian(0.4),
+    ];
+
+    #[test]
+    fn windows_have_a_mean_of_one() {
+        for kind in KINDS {
+            let window = kind.coefficients(1024);
+            let mean = window.iter().sum::<f32>() / window.len() as f32;
+            assert!((mean - 1.0).abs() < 1e-4, "{}: mean {}", kind, mean);
+        }
+    }
+
+    #[test]
+    fn periodic_hann_has_no_repeated_endpoint() {
+        let size = 16;
+        let window = WindowKind::Hann.compute(size);
+        assert_eq!(window[0], 0.0);
+        // the last sample is one step short of the zero the next period starts on
+        assert!(window[size - 1] > 0.0);
 
-    let hann_coeffs = match hann_window(window_size) {
-        Ok(coeffs) => coeffs,
-        Err(err) => panic!("{}", err),
-    };
+        let peak = window.iter().cloned().fold(f32::MIN, f32::max);
+        assert_eq!(window[size / 2], peak);
+        for i in 1..size {
+            assert!((window[i] - window[size - i]).abs() < 1e-6);
+        }
+    }
 
-    let end_pos = samples.len() - window_size;
-    for pos in (0..end_pos).step_by(hop_size) {
-        let chunk = &samples[pos..(pos + window_size)];
-        let mut window_chunk: Vec<f32> = vec![0.0; window_size];
-        for i in 0..window_size - 1 {
-            window_chunk[i] = chunk[i] * hann_coeffs[i];
+    #[test]
+    fn names_round_trip() {
+        for kind in KINDS {
+            assert_eq!(kind.to_string().parse::<WindowKind>(), Ok(kind));
         }
-        windowed_samples.push(window_chunk);
+        assert_eq!("kaiser:6".parse(), Ok(WindowKind::Kaiser(6.0)));
+        assert_eq!(WindowKind::Kaiser(6.0).to_string(), "kaiser:6");
+        assert_eq!("Blackman-Harris".parse(), Ok(WindowKind::BlackmanHarris));
+    }
+
+    #[test]
+    fn rejects_bad_names_and_parameters() {
+        assert!("hann:3".parse::<WindowKind>().is_err());
+        assert!("kaiser:-1".parse::<WindowKind>().is_err());
+        assert!("kaiser:x".parse::<WindowKind>().is_err());
+        assert!("triangle".parse::<WindowKind>().is_err());
+    }
+
+    #[test]
+    fn cache_shares_the_same_window() {
+        let first = WindowKind::Gaussian(0.3).coefficients(512);
+        let second = WindowKind::Gaussian(0.3).coefficients(512);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let other = WindowKind::Gaussian(0.3).coefficients(256);
+        assert!(!Arc::ptr_eq(&first, &other));
+    }
+
+    fn assert_rejected(config: StftConfig, reason: &str) {
+        let err = config.validate().unwrap_err();
+        assert!(err.contains(reason), "{:?}: {}", config, err);
+    }
+
+    #[test]
+    fn validate_rejects_each_bad_config() {
+        let config = StftConfig::new(1024, WindowKind::Hann);
+        assert!(config.validate().is_ok());
+
+        assert_rejected(StftConfig::new(2, WindowKind::Hann), "too small");
+        assert_rejected(config.with_hop_size(0), "hop size 0");
+        assert_rejected(config.with_hop_size(1025), "hop size 1025");
+        assert_rejected(config.with_fft_size(512), "fft size 512");
+        assert_rejected(
+            config.with_hop_size(1024).with_phase_refinement(true),
+            "phase refinement",
+        );
+        // the same hop is fine without phase refinement, or with more padding
+        assert!(config.with_hop_size(1024).validate().is_ok());
+        assert!(
+            config
+                .with_hop_size(1024)
+                .with_fft_size(2048)
+                .with_phase_refinement(true)
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn window_starts_cover_full_windows_only() {
+        let config = StftConfig::new(8, WindowKind::Hann).with_hop_size(4);
+        assert_eq!(config.window_starts(7).count(), 0);
+        assert_eq!(config.window_starts(0).count(), 0);
+        assert_eq!(config.window_starts(8).collect::<Vec<_>>(), [0]);
+        assert_eq!(config.window_starts(17).collect::<Vec<_>>(), [0, 4, 8]);
     }
-    Ok(())
 }
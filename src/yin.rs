@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex, num_complex::Complex};
+
+use crate::fft::parabolic_offset;
+use crate::notes::{BandResult, Pitch};
+
+// lowest and highest pitch tracked
+const MIN_FREQUENCY: f32 = 50.0;
+const MAX_FREQUENCY: f32 = 2000.0;
+// dips of the normalized difference below this count as periodic
+const THRESHOLD: f32 = 0.15;
+// frames quieter than this rms are unvoiced, about -50 dBFS
+const SILENCE_RMS: f32 = 0.003;
+
+/// Time-domain pitch tracker after de Cheveigné and Kawahara's YIN. Frames
+/// of `frame_size` samples hold half a frame of lags, so the lowest pitch is
+/// limited by the frame size as well.
+pub struct Yin {
+    sample_rate: f32,
+    frame_size: usize,
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    // scratch reused between frames
+    signal: Vec<f32>,
+    window: Vec<f32>,
+    signal_spectrum: Vec<Complex<f32>>,
+    window_spectrum: Vec<Complex<f32>>,
+    correlation: Vec<f32>,
+    difference: Vec<f32>,
+}
+
+impl Yin {
+    pub fn new(sample_rate: f32, frame_size: usize) -> Self {
+        // long enough that the circular correlation doesn't wrap around
+        let fft_size = (2 * frame_size).next_power_of_two();
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(fft_size);
+        let inverse = planner.plan_fft_inverse(fft_size);
+
+        Self {
+            sample_rate,
+            frame_size,
+            signal: forward.make_input_vec(),
+            window: forward.make_input_vec(),
+            signal_spectrum: forward.make_output_vec(),
+            window_spectrum: forward.make_output_vec(),
+            correlation: inverse.make_output_vec(),
+            difference: vec![0.0; frame_size / 2],
+            forward,
+            inverse,
+        }
+    }
+
+    /// Frequency of the first `frame_size` samples, `None` if they are
+    /// unvoiced or too short.
+    pub fn detect(&mut self, samples: &[f32]) -> Option<f32> {
+        let frame = samples.get(..self.frame_size)?;
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+        if rms < SILENCE_RMS {
+            return None;
+        }
+
+        let max_lag = (self.frame_size / 2).min((self.sample_rate / MIN_FREQUENCY) as usize);
+        let min_lag = ((self.sample_rate / MAX_FREQUENCY) as usize).max(2);
+        if min_lag + 1 >= max_lag {
+            return None;
+        }
+        self.difference_function(frame, max_lag);
+        self.cumulative_mean_normalize(max_lag);
+
+        // first dip below the threshold, followed down to its minimum
+        let difference = &self.difference;
+        let mut lag = (min_lag..max_lag).find(|&lag| difference[lag] < THRESHOLD)?;
+        while lag + 1 < max_lag && difference[lag + 1] < difference[lag] {
+            lag += 1;
+        }
+
+        // values from max_lag on weren't computed for this frame
+        let refined = lag as f32 + parabolic_offset(&difference[..max_lag], lag);
+        Some(self.sample_rate / refined)
+    }
+
+    /// The pitch of the first `frame_size` samples as a band result, empty
+    /// when unvoiced.
+    pub fn detect_band(&mut self, samples: &[f32]) -> BandResult {
+        BandResult {
+            pitches: self
+                .detect(samples)
+                .and_then(Pitch::from_frequency)
+                .into_iter()
+                .collect(),
+            chord: None,
+        }
+    }
+
+    // squared difference between the first half of the frame and the frame
+    // shifted by each lag, from energies and one fft cross-correlation
+    fn difference_function(&mut self, frame: &[f32], max_lag: usize) {
+        let window_size = self.frame_size - max_lag;
+
+        self.signal.fill(0.0);
+        self.signal[..frame.len()].copy_from_slice(frame);
+        self.window.fill(0.0);
+        self.window[..window_size].copy_from_slice(&frame[..window_size]);
+
+        // the inputs are left alone, errors only come from mismatched lengths
+        let _ = self
+            .forward
+            .process(&mut self.signal, &mut self.signal_spectrum);
+        let _ = self
+            .forward
+            .process(&mut self.window, &mut self.window_spectrum);
+        for (signal, window) in self.signal_spectrum.iter_mut().zip(&self.window_spectrum) {
+            *signal *= window.conj();
+        }
+        let _ = self
+            .inverse
+            .process(&mut self.signal_spectrum, &mut self.correlation);
+        let scale = 1.0 / self.correlation.len() as f32;
+
+        // energy of the window at lag 0 and of the shifted window at each lag
+        let window_energy: f32 = frame[..window_size].iter().map(|s| s * s).sum();
+        let mut shifted_energy = window_energy;
+        self.difference[0] = 0.0;
+        for lag in 1..max_lag {
+            shifted_energy += frame[lag + window_size - 1].powi(2) - frame[lag - 1].powi(2);
+            self.difference[lag] =
+                window_energy + shifted_energy - 2.0 * self.correlation[lag] * scale;
+        }
+    }
+
+    fn cumulative_mean_normalize(&mut self, max_lag: usize) {
+        self.difference[0] = 1.0;
+        let mut running_sum = 0.0;
+        for lag in 1..max_lag {
+            running_sum += self.difference[lag];
+            self.difference[lag] = if running_sum > 0.0 {
+                self.difference[lag] * lag as f32 / running_sum
+            } else {
+                1.0
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    fn sine(frequency: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.5 * (2.0 * PI * frequency * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    #[test]
+    fn finds_a_sine_within_a_few_cents() {
+        let mut yin = Yin::new(SAMPLE_RATE, 2048);
+        for frequency in [82.41, 220.0, 440.0, 1318.51] {
+            let detected = yin.detect(&sine(frequency, 2048)).unwrap();
+            let cents = 1200.0 * (detected / frequency).log2();
+            assert!(
+                cents.abs() < 5.0,
+                "{} Hz found at {} Hz",
+                frequency,
+                detected
+            );
+        }
+    }
+
+    #[test]
+    fn silence_and_short_frames_are_unvoiced() {
+        let mut yin = Yin::new(SAMPLE_RATE, 2048);
+        assert_eq!(yin.detect(&vec![0.0; 2048]), None);
+        assert_eq!(yin.detect(&sine(440.0, 1024)), None);
+    }
+}